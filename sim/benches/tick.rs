@@ -0,0 +1,29 @@
+#![feature(test)]
+
+extern crate test;
+
+use rand::{SeedableRng, rngs::SmallRng};
+use sim::internet::{CustomNode, NetAddr, ScheduleOrder};
+use sim::node::NodeAction;
+use sim::{NetSim, Node};
+
+/// Network of `num_nodes` seeded nodes where every node after the first bootstraps off of NodeID 0
+fn bootstrapped_net(num_nodes: u32, seed: u64, ticks: usize) -> (NetSim<Node>, SmallRng) {
+	let mut rng = SmallRng::seed_from_u64(seed);
+	let mut internet = NetSim::new();
+	for node_id in 0..num_nodes {
+		let node = Node::new(node_id, internet.lease()).with_seed(seed + node_id as u64);
+		internet.add_node(node, &mut rng);
+	}
+	for net_addr in 1..num_nodes as NetAddr {
+		internet.node_mut(net_addr).unwrap().action(NodeAction::Bootstrap(0, 0));
+	}
+	internet.tick(ticks, &mut rng);
+	(internet, rng)
+}
+
+#[bench]
+fn tick_100_nodes(b: &mut test::Bencher) {
+	let (mut internet, mut rng) = bootstrapped_net(100, 1, 1000);
+	b.iter(|| internet.tick_round(ScheduleOrder::RoundRobin, &mut rng));
+}
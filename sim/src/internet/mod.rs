@@ -1,18 +1,19 @@
 //#![allow(dead_code)]
 
-use std::{collections::HashMap, fmt::Debug, fs::File, hash::Hash, io::{BufRead, BufReader, Write}};
+use std::{collections::{BTreeMap, HashMap}, fmt::Debug, fs::File, hash::Hash, io::{BufRead, BufReader, Write}};
 use std::any::Any;
 use std::ops::Range;
 
 use anyhow::Context;
 use rand::Rng;
 use serde::{Deserialize, de::DeserializeOwned};
-use smallvec::SmallVec;
 
 mod router;
 use router::NetSimRouter;
+pub mod transport;
+pub use transport::{Transport, TransportPacketVec};
 
-use crate::{Node, node::RouteCoord};
+use crate::{Node, node::{DhtKey, NodeID, RouteCoord}};
 
 pub const FIELD_DIMENSIONS: (Range<i32>, Range<i32>) = (-320..320, -130..130);
 
@@ -28,8 +29,12 @@ pub enum InternetError {
 pub enum NetSimRequest<CN: CustomNode + ?Sized> {
 	RouteCoordDHTRead(CN::CustomNodeUUID),
 	RouteCoordDHTWrite(CN::CustomNodeUUID, RouteCoord),
+	RouteCoordDHTDelete(CN::CustomNodeUUID),
 	RouteCoordDHTReadResponse(CN::CustomNodeUUID, Option<RouteCoord>),
 	RouteCoordDHTWriteResponse(Option<(CN::CustomNodeUUID, RouteCoord)>),
+	/// Read up to `usize` entries whose region keys are closest to a `DhtKey`, see `Node::coordinate_to_dht_key`
+	RouteCoordDHTRegionRead(DhtKey, usize),
+	RouteCoordDHTRegionResponse(DhtKey, Vec<(CN::CustomNodeUUID, RouteCoord)>),
 	RandomNodeRequest(u32),
 	RandomNodeResponse(u32, Option<CN::CustomNodeUUID>),
 }
@@ -46,32 +51,132 @@ impl<CN: CustomNode> NetSimPacket<CN> {
 }
 
 pub type NetAddr = u128;
-pub type NetSimPacketVec<CN> = SmallVec<[NetSimPacket<CN>; 32]>;
+pub type NetSimPacketVec<CN> = TransportPacketVec<NetSimPacket<CN>>;
 
 pub trait CustomNode: Debug + Default {
 	type CustomNodeAction;
 	type CustomNodeUUID: Debug + Hash + Eq + Clone + serde::Serialize + DeserializeOwned;
+	/// Packets this node is ticked with, it runs unchanged over any `Transport` carrying them (NetSim requires `NetSimPacket<Self>`)
+	type Packet;
 	fn net_addr(&self) -> NetAddr;
 	fn unique_id(&self) -> Self::CustomNodeUUID;
-	fn tick(&mut self, incoming: NetSimPacketVec<Self>) -> NetSimPacketVec<Self>;
+	fn tick(&mut self, incoming: TransportPacketVec<Self::Packet>) -> TransportPacketVec<Self::Packet>;
 	fn action(&mut self, action: Self::CustomNodeAction);
 	fn as_any(&self) -> &dyn Any;
 	fn set_deus_ex_data(&mut self, data: Option<RouteCoord>);
+	/// Jump this node's clock forward by a number of ticks without doing anything else, used to simulate clock skew
+	fn offset_clock(&mut self, _ticks: usize) {}
 }
 
 
+/// Order in which nodes are ticked each round
+#[derive(Debug, Clone, Copy)]
+pub enum ScheduleOrder {
+	/// Tick nodes in ascending NetAddr order
+	RoundRobin,
+	/// Tick nodes in an order shuffled by the passed rng (reproducible with a seeded rng)
+	Shuffled,
+}
+
+/// Clock drift of a single node relative to the simulator
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClockSkew {
+	/// Number of node ticks per simulator round, e.g. 1.01 for a node whose clock runs 1% fast
+	pub rate: f64,
+	/// Fractional ticks carried over between rounds
+	accumulated: f64,
+}
+impl ClockSkew {
+	/// Number of times the node should be ticked this round
+	fn ticks_this_round(&mut self) -> usize {
+		self.accumulated += self.rate.max(0.0);
+		let ticks = self.accumulated.floor();
+		self.accumulated -= ticks;
+		ticks as usize
+	}
+}
+
+/// Statistics collected over a single round of ticking every node
+#[derive(Debug, Default, Clone)]
+pub struct RoundStats {
+	pub round: usize,
+	pub nodes_ticked: usize,
+	pub packets_received: usize,
+	pub packets_sent: usize,
+	pub requests: usize,
+}
+
+/// Coordinate DHT sharded by region: entries are stored under the Hilbert key of their coordinate so nearby coordinates share shards
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "UUID: serde::Serialize + DeserializeOwned + Hash + Eq")]
+pub struct RouteCoordDHT<UUID> {
+	shards: BTreeMap<DhtKey, Vec<(UUID, RouteCoord)>>,
+	/// Shard each stored id lives in
+	keys: HashMap<UUID, DhtKey>,
+}
+impl<UUID> Default for RouteCoordDHT<UUID> {
+	fn default() -> Self { Self { shards: BTreeMap::new(), keys: HashMap::new() } }
+}
+impl<UUID: Hash + Eq + Clone> RouteCoordDHT<UUID> {
+	pub fn get(&self, id: &UUID) -> Option<RouteCoord> {
+		let key = self.keys.get(id)?;
+		self.shards.get(key)?.iter().find(|(entry_id, _)| entry_id == id).map(|(_, coord)| *coord)
+	}
+	pub fn insert(&mut self, id: UUID, route_coord: RouteCoord) -> Option<RouteCoord> {
+		let old = self.remove(&id);
+		let key = Node::coordinate_to_dht_key(route_coord);
+		self.shards.entry(key).or_default().push((id.clone(), route_coord));
+		self.keys.insert(id, key);
+		old
+	}
+	pub fn remove(&mut self, id: &UUID) -> Option<RouteCoord> {
+		let key = self.keys.remove(id)?;
+		let shard = self.shards.get_mut(&key)?;
+		let pos = shard.iter().position(|(entry_id, _)| entry_id == id)?;
+		let (_, coord) = shard.swap_remove(pos);
+		if shard.is_empty() { self.shards.remove(&key); }
+		Some(coord)
+	}
+	/// Up to `count` entries with keys closest to `key`, walking outward from it in both directions
+	pub fn region(&self, key: DhtKey, count: usize) -> Vec<(UUID, RouteCoord)> {
+		let mut above = self.shards.range(key..).flat_map(|(k, shard)| shard.iter().map(move |e| (*k, e))).peekable();
+		let mut below = self.shards.range(..key).rev().flat_map(|(k, shard)| shard.iter().map(move |e| (*k, e))).peekable();
+		let mut entries = Vec::new();
+		while entries.len() < count {
+			let take_above = match (above.peek(), below.peek()) {
+				(Some((a, _)), Some((b, _))) => a - key <= key - b,
+				(Some(_), None) => true,
+				(None, Some(_)) => false,
+				(None, None) => break,
+			};
+			let (_, entry) = if take_above { above.next() } else { below.next() }.unwrap();
+			entries.push(entry.clone());
+		}
+		entries
+	}
+	pub fn ids(&self) -> impl Iterator<Item = &UUID> { self.keys.keys() }
+	pub fn len(&self) -> usize { self.keys.len() }
+	pub fn is_empty(&self) -> bool { self.keys.is_empty() }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NetSim<CN: CustomNode> {
 	pub nodes: HashMap<NetAddr, CN>,
 	pub router: NetSimRouter<CN>,
-	route_coord_dht: HashMap<CN::CustomNodeUUID, RouteCoord>,
+	route_coord_dht: RouteCoordDHT<CN::CustomNodeUUID>,
+	#[serde(default)]
+	clocks: HashMap<NetAddr, ClockSkew>,
+	#[serde(skip)]
+	rounds: usize,
 }
-impl<CN: CustomNode> NetSim<CN> {
+impl<CN: CustomNode<Packet = NetSimPacket<CN>>> NetSim<CN> {
 	pub fn new() -> NetSim<CN> {
 		NetSim {
 			nodes: HashMap::new(),
 			router: NetSimRouter::new(FIELD_DIMENSIONS),
-			route_coord_dht: HashMap::new(),
+			route_coord_dht: RouteCoordDHT::default(),
+			clocks: HashMap::new(),
+			rounds: 0,
 		}
 	}
 	pub fn from_reader<CND: CustomNode + DeserializeOwned>(reader: impl BufRead) -> anyhow::Result<NetSim<CND>> {
@@ -82,54 +187,120 @@ impl<CN: CustomNode> NetSim<CN> {
 		self.router.add_node(node.net_addr(), rng);
 		self.nodes.insert(node.net_addr(), node);
 	}
-	pub fn del_node(&mut self, net_addr: NetAddr) { self.nodes.remove(&net_addr); }
+	pub fn del_node(&mut self, net_addr: NetAddr) { self.nodes.remove(&net_addr); self.clocks.remove(&net_addr); }
+	/// Skew a node's clock: jump it `offset` ticks ahead now and from then on tick it `rate` times per round on average
+	pub fn set_clock_skew(&mut self, net_addr: NetAddr, offset: usize, rate: f64) -> Result<(), InternetError> {
+		self.node_mut(net_addr)?.offset_clock(offset);
+		self.clocks.insert(net_addr, ClockSkew { rate, accumulated: 0.0 });
+		Ok(())
+	}
 	pub fn node_mut(&mut self, net_addr: NetAddr) -> Result<&mut CN, InternetError> { self.nodes.get_mut(&net_addr).ok_or(InternetError::NoNodeError { net_addr }) }
 	pub fn node(&self, net_addr: NetAddr) -> Result<&CN, InternetError> { self.nodes.get(&net_addr).ok_or(InternetError::NoNodeError { net_addr }) }
 	pub fn tick(&mut self, ticks: usize, rng: &mut impl Rng) {
-		//let packets_tmp = Vec::new();
 		for _ in 0..ticks {
-			for (&node_net_addr, node) in self.nodes.iter_mut() {
-				// Get Packets going to node
-				let incoming_packets = self.router.tick_node(node_net_addr);
-				// Get packets coming from node
-				let mut outgoing_packets = node.tick(incoming_packets);
-
-				// Make outgoing packets have the correct return address or parse request
-				for packet in &mut outgoing_packets {
-					packet.src_addr = node_net_addr;
-					if let Some(request) = &packet.request {
-						log::debug!("NetAddr({:?}) Requested NetSimRequest::{:?}", node_net_addr, request);
-						packet.request = Some(match *request {
-							NetSimRequest::RouteCoordDHTRead(ref node_id) => {
-								let node_id = node_id.clone();
-								packet.dest_addr = packet.src_addr;
-								let route = self.route_coord_dht.get(&node_id).map(|r|r.clone());
-								NetSimRequest::RouteCoordDHTReadResponse(node_id, route)
-							}
-							NetSimRequest::RouteCoordDHTWrite(ref node_id, route_coord) => {
-								packet.dest_addr = packet.src_addr;
-								let old_route = self.route_coord_dht.insert(node_id.clone(), route_coord);
-								NetSimRequest::RouteCoordDHTWriteResponse( old_route.map(|r|(node_id.clone(), r) ))
-							}
-							NetSimRequest::RandomNodeRequest(unique_id) => {
-								use rand::prelude::IteratorRandom;
-								let id = self.route_coord_dht.iter().choose(rng).map(|(id,_)|id.clone());
-								NetSimRequest::RandomNodeResponse(unique_id, id)
-							}
-							_ => { log::error!("Invalid NetSimRequest variant"); unimplemented!() },
-						});
+			self.tick_round(ScheduleOrder::RoundRobin, rng);
+		}
+	}
+	/// Tick every node exactly once in a deterministic order and deliver outgoing packets in that same order
+	pub fn tick_round(&mut self, order: ScheduleOrder, rng: &mut impl Rng) -> RoundStats {
+		let mut schedule: Vec<NetAddr> = self.nodes.keys().cloned().collect();
+		schedule.sort_unstable();
+		if let ScheduleOrder::Shuffled = order {
+			use rand::seq::SliceRandom;
+			schedule.shuffle(rng);
+		}
+
+		let mut stats = RoundStats { round: self.rounds, ..Default::default() };
+		for node_net_addr in schedule {
+			self.tick_node(node_net_addr, &mut stats, rng);
+		}
+		self.rounds += 1;
+		stats
+	}
+	fn tick_node(&mut self, node_net_addr: NetAddr, stats: &mut RoundStats, rng: &mut impl Rng) {
+		let node = if let Some(node) = self.nodes.get_mut(&node_net_addr) { node } else { return };
+		// Slow clocks skip rounds (leaving their packets queued in the router) and fast clocks get extra ticks
+		let node_ticks = self.clocks.get_mut(&node_net_addr).map_or(1, |clock| clock.ticks_this_round());
+		if node_ticks == 0 { return }
+		// Get Packets going to node
+		let incoming_packets = self.router.tick_node(node_net_addr);
+		stats.packets_received += incoming_packets.len();
+		// Get packets coming from node
+		let mut outgoing_packets = node.tick(incoming_packets);
+		for _ in 1..node_ticks {
+			outgoing_packets.extend(node.tick(NetSimPacketVec::new()));
+		}
+		stats.nodes_ticked += 1;
+
+		// Make outgoing packets have the correct return address or parse request
+		for packet in &mut outgoing_packets {
+			packet.src_addr = node_net_addr;
+			if let Some(request) = &packet.request {
+				log::debug!("NetAddr({:?}) Requested NetSimRequest::{:?}", node_net_addr, request);
+				stats.requests += 1;
+				packet.request = Some(match *request {
+					NetSimRequest::RouteCoordDHTRead(ref node_id) => {
+						let node_id = node_id.clone();
+						packet.dest_addr = packet.src_addr;
+						let route = self.route_coord_dht.get(&node_id);
+						NetSimRequest::RouteCoordDHTReadResponse(node_id, route)
+					}
+					NetSimRequest::RouteCoordDHTWrite(ref node_id, route_coord) => {
+						packet.dest_addr = packet.src_addr;
+						let old_route = self.route_coord_dht.insert(node_id.clone(), route_coord);
+						NetSimRequest::RouteCoordDHTWriteResponse( old_route.map(|r|(node_id.clone(), r) ))
+					}
+					NetSimRequest::RouteCoordDHTDelete(ref node_id) => {
+						packet.dest_addr = packet.src_addr;
+						let old_route = self.route_coord_dht.remove(node_id);
+						NetSimRequest::RouteCoordDHTWriteResponse( old_route.map(|r|(node_id.clone(), r) ))
 					}
-				}
-				// Send packets through the router
-				self.router.add_packets(outgoing_packets, rng);
-				/* if let Some(rn) = self.router.node_map.get(&node_net_addr) {
-					let cheat_coord = rn.position.clone().map(|s|s.floor() as i64);
-					node.set_deus_ex_data( Some(cheat_coord) ) } */
+					NetSimRequest::RouteCoordDHTRegionRead(key, count) => {
+						packet.dest_addr = packet.src_addr;
+						NetSimRequest::RouteCoordDHTRegionResponse(key, self.route_coord_dht.region(key, count))
+					}
+					NetSimRequest::RandomNodeRequest(unique_id) => {
+						use rand::prelude::IteratorRandom;
+						let id = self.route_coord_dht.ids().choose(rng).cloned();
+						NetSimRequest::RandomNodeResponse(unique_id, id)
+					}
+					_ => { log::error!("Invalid NetSimRequest variant"); unimplemented!() },
+				});
+			} else {
+				stats.packets_sent += 1;
 			}
 		}
+		// Send packets through the router
+		self.router.add_packets(outgoing_packets, rng);
+		/* if let Some(rn) = self.router.node_map.get(&node_net_addr) {
+			let cheat_coord = rn.position.clone().map(|s|s.floor() as i64);
+			node.set_deus_ex_data( Some(cheat_coord) ) } */
+	}
+}
+/// Route coordinates of a set of nodes, sorted by NodeID
+pub fn collect_coordinates<'a>(nodes: impl IntoIterator<Item = &'a Node>) -> Vec<(NodeID, Option<RouteCoord>)> {
+	let mut coords: Vec<(NodeID, Option<RouteCoord>)> = nodes.into_iter().map(|node| (node.node_id, node.route_coord)).collect();
+	coords.sort_unstable_by_key(|(node_id, _)| *node_id);
+	coords
+}
+
+/// Time-series of every node's route coordinate, used to animate coordinate convergence
+#[derive(Debug, Default, Clone)]
+pub struct CoordinateRecorder {
+	/// (Round number, coordinates of all nodes at that round)
+	pub series: Vec<(usize, Vec<(NodeID, Option<RouteCoord>)>)>,
+}
+impl CoordinateRecorder {
+	pub fn new() -> Self { Self::default() }
+	pub fn record(&mut self, internet: &NetSim<Node>) {
+		self.series.push((internet.rounds, collect_coordinates(internet.nodes.values())));
 	}
 }
+
 impl NetSim<Node> {
+	pub fn collect_coordinates(&self) -> Vec<(NodeID, Option<RouteCoord>)> {
+		collect_coordinates(self.nodes.values())
+	}
 	pub fn save(&self, filepath: &str) -> Result<(), InternetError> {
 		let mut file = File::create(filepath).context("failed to create file (check perms) at {}")?;
 		let data = bincode::serialize(&self).context("failed to serialize network")?;
@@ -141,4 +312,103 @@ impl NetSim<Node> {
 		*self = bincode::deserialize_from(BufReader::new(file)).context("failed to deserialize network")?;
 		Ok(())
 	}
-}
\ No newline at end of file
+	/// Move a node to a new NetAddr, keeping its position and clock skew, and have it tell its peers with `Node::set_net_addr`.
+	/// Packets still in flight to the old NetAddr are lost
+	pub fn move_node(&mut self, net_addr: NetAddr, new_net_addr: NetAddr, rng: &mut impl Rng) -> Result<(), InternetError> {
+		if self.nodes.contains_key(&new_net_addr) {
+			return Err(anyhow!("NetAddr {} is already in use", new_net_addr).into());
+		}
+		let mut node = self.nodes.remove(&net_addr).ok_or(InternetError::NoNodeError { net_addr })?;
+		let mut outgoing = NetSimPacketVec::new();
+		let result = node.set_net_addr(new_net_addr, &mut outgoing);
+		self.nodes.insert(new_net_addr, node);
+		if let Some(mut router_node) = self.router.node_map.remove(&net_addr) {
+			router_node.uuid = new_net_addr;
+			self.router.node_map.insert(new_net_addr, router_node);
+		}
+		self.router.packet_map.remove(&net_addr);
+		if let Some(clock) = self.clocks.remove(&net_addr) { self.clocks.insert(new_net_addr, clock); }
+		for packet in &mut outgoing { packet.src_addr = new_net_addr; }
+		self.router.add_packets(outgoing, rng);
+		result.context("failed to tell peers about the new NetAddr")?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{SeedableRng, rngs::SmallRng};
+
+	use super::*;
+	use crate::node::{NodeAction, NodeEvent};
+
+	/// Bytes of every packet in flight after each round, sorted within a round
+	fn packet_log(seed: u64) -> Vec<Vec<(NetAddr, NetAddr, Vec<u8>)>> {
+		let mut rng = SmallRng::seed_from_u64(seed);
+		let mut internet = NetSim::new();
+		for node_id in 0..5 {
+			let mut node = Node::new(node_id, internet.lease()).with_seed(node_id as u64);
+			if node_id != 0 { node.action(NodeAction::Bootstrap(0, 0)); }
+			internet.add_node(node, &mut rng);
+		}
+		(0..2000).map(|_| {
+			internet.tick_round(ScheduleOrder::Shuffled, &mut rng);
+			let mut round: Vec<(NetAddr, NetAddr, Vec<u8>)> = internet.router.packet_map.values().flatten()
+				.map(|(packet, _)| (packet.src_addr, packet.dest_addr, packet.data.clone()))
+				.collect();
+			round.sort();
+			round
+		}).collect()
+	}
+
+	#[test]
+	fn same_seed_reproduces_packet_log() {
+		let log = packet_log(3);
+		assert!(log.iter().any(|round| !round.is_empty()));
+		assert_eq!(log, packet_log(3));
+	}
+
+	#[test]
+	fn moved_node_stays_reachable() {
+		let mut rng = SmallRng::seed_from_u64(0);
+		let mut internet = NetSim::new();
+		for node_id in 0..2 {
+			let mut node = Node::new(node_id, internet.lease()).with_seed(node_id as u64);
+			if node_id != 0 { node.action(NodeAction::Bootstrap(0, 0)); }
+			internet.add_node(node, &mut rng);
+		}
+		internet.tick(1000, &mut rng);
+		internet.move_node(1, 50, &mut rng).unwrap();
+		assert!(internet.node(1).is_err());
+		internet.tick(200, &mut rng);
+
+		let node = internet.node(0).unwrap();
+		let node_idx = node.index_by_node_id(&1).unwrap();
+		assert_eq!(node.remote(node_idx).unwrap().session().unwrap().direct().unwrap().net_addr, 50);
+		internet.node_mut(0).unwrap().action(NodeAction::Notify(1, 7));
+		internet.tick(200, &mut rng);
+		assert!(internet.node(50).unwrap().events().contains(&NodeEvent::Notify { sender: 0, data: 7 }));
+	}
+
+	#[test]
+	fn recorder_tracks_every_round() {
+		let mut rng = SmallRng::seed_from_u64(0);
+		let mut internet = NetSim::new();
+		for node_id in 0..3 {
+			let mut node = Node::new(node_id, internet.lease()).with_seed(node_id as u64);
+			node.route_coord = Some(RouteCoord::new(node_id as i64 * 10, 0));
+			internet.add_node(node, &mut rng);
+		}
+		let mut recorder = CoordinateRecorder::new();
+		for _ in 0..50 {
+			internet.tick_round(ScheduleOrder::RoundRobin, &mut rng);
+			recorder.record(&internet);
+		}
+		assert_eq!(recorder.series.len(), 50);
+		assert!(recorder.series.windows(2).all(|w| w[1].0 == w[0].0 + 1));
+		// Nothing moves the coordinates of idle nodes, so every snapshot is identical
+		let expected: Vec<(NodeID, Option<RouteCoord>)> = (0..3).map(|i| (i, Some(RouteCoord::new(i as i64 * 10, 0)))).collect();
+		assert!(recorder.series.iter().all(|(_, coords)| *coords == expected));
+		assert_eq!(internet.collect_coordinates(), expected);
+	}
+}
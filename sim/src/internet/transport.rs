@@ -0,0 +1,151 @@
+//! Abstraction over how a node's packets are actually delivered, so node logic can run over the simulator or a real network
+
+use std::io;
+use std::marker::PhantomData;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+
+use smallvec::SmallVec;
+
+use super::{CustomNode, NetAddr, NetSimPacket};
+
+/// Batch of packets passed into and out of a single tick
+pub type TransportPacketVec<P> = SmallVec<[P; 32]>;
+
+/// Delivers packets to and from a single node
+pub trait Transport {
+	/// Packet type this transport carries, a node can run over any transport carrying its `CustomNode::Packet`
+	type Packet;
+	/// Packets that have arrived for the node since the last call
+	fn receive(&mut self) -> TransportPacketVec<Self::Packet>;
+	/// Deliver packets produced by the node
+	fn send(&mut self, packets: TransportPacketVec<Self::Packet>);
+}
+
+/// Run a single tick of a node over a transport
+pub fn tick_over<CN: CustomNode, T: Transport<Packet = CN::Packet>>(node: &mut CN, transport: &mut T) {
+	let incoming = transport.receive();
+	let outgoing = node.tick(incoming);
+	transport.send(outgoing);
+}
+
+/// In-memory transport, packets are pushed into `incoming` and collected from `outgoing` by whatever is driving the node (e.g. NetSim or a test)
+#[derive(Derivative)]
+#[derivative(Debug, Default(bound = ""))]
+pub struct QueueTransport<P> {
+	pub incoming: TransportPacketVec<P>,
+	pub outgoing: TransportPacketVec<P>,
+}
+impl<P> Transport for QueueTransport<P> {
+	type Packet = P;
+	fn receive(&mut self) -> TransportPacketVec<P> {
+		std::mem::take(&mut self.incoming)
+	}
+	fn send(&mut self, packets: TransportPacketVec<P>) {
+		self.outgoing.extend(packets);
+	}
+}
+
+/// Transport over a UDP socket. NetAddrs are interpreted as IPv6 addresses (IPv4 is mapped) that all listen on `port`.
+/// Simulator requests (DHT, random node) have no network equivalent yet and are dropped
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct UdpTransport<CN: CustomNode> {
+	socket: UdpSocket,
+	port: u16,
+	#[derivative(Debug = "ignore")]
+	node: PhantomData<CN>,
+}
+impl<CN: CustomNode> UdpTransport<CN> {
+	pub fn bind(net_addr: NetAddr, port: u16) -> io::Result<Self> {
+		let socket = UdpSocket::bind(SocketAddrV6::new(Ipv6Addr::from(net_addr), port, 0, 0))?;
+		socket.set_nonblocking(true)?;
+		Ok(Self { socket, port, node: PhantomData })
+	}
+	fn net_addr_from(addr: SocketAddr) -> NetAddr {
+		match addr {
+			SocketAddr::V4(v4) => u128::from(v4.ip().to_ipv6_mapped()),
+			SocketAddr::V6(v6) => u128::from(*v6.ip()),
+		}
+	}
+}
+impl<CN: CustomNode> Transport for UdpTransport<CN> {
+	type Packet = NetSimPacket<CN>;
+	fn receive(&mut self) -> TransportPacketVec<NetSimPacket<CN>> {
+		let dest_addr = self.socket.local_addr().map(Self::net_addr_from).unwrap_or_default();
+		let mut packets = TransportPacketVec::new();
+		let mut buf = [0u8; 65536];
+		loop {
+			match self.socket.recv_from(&mut buf) {
+				Ok((len, src)) => packets.push(NetSimPacket {
+					dest_addr,
+					data: buf[..len].to_vec(),
+					src_addr: Self::net_addr_from(src),
+					request: None,
+				}),
+				Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+				Err(err) => { log::error!("Failed to receive UDP packet: {:?}", err); break }
+			}
+		}
+		packets
+	}
+	fn send(&mut self, packets: TransportPacketVec<NetSimPacket<CN>>) {
+		for packet in packets {
+			if packet.request.is_some() {
+				log::warn!("UdpTransport can't fulfill simulator requests, dropping: {:?}", packet.request);
+				continue;
+			}
+			let dest = SocketAddrV6::new(Ipv6Addr::from(packet.dest_addr), self.port, 0, 0);
+			if let Err(err) = self.socket.send_to(&packet.data, dest) {
+				log::error!("Failed to send UDP packet to {}: {:?}", dest, err);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{cell::RefCell, rc::Rc};
+
+	use super::*;
+	use crate::node::{Node, NodeAction, SessionKind};
+
+	/// Packets in flight between mock transports, delivered by `dest_addr` on the recipient's next receive
+	type Wire = Rc<RefCell<Vec<NetSimPacket<Node>>>>;
+
+	struct MockTransport {
+		net_addr: NetAddr,
+		wire: Wire,
+	}
+	impl Transport for MockTransport {
+		type Packet = NetSimPacket<Node>;
+		fn receive(&mut self) -> TransportPacketVec<Self::Packet> {
+			let net_addr = self.net_addr;
+			self.wire.borrow_mut().drain_filter(|packet| packet.dest_addr == net_addr).collect()
+		}
+		fn send(&mut self, packets: TransportPacketVec<Self::Packet>) {
+			let mut wire = self.wire.borrow_mut();
+			// The mock has no DHT, simulator requests are dropped
+			for mut packet in packets.into_iter().filter(|packet| packet.request.is_none()) {
+				packet.src_addr = self.net_addr;
+				wire.push(packet);
+			}
+		}
+	}
+
+	#[test]
+	fn node_bootstraps_over_mock_transport() {
+		let wire = Wire::default();
+		let mut nodes = vec![
+			Node::new(0, 0).with_seed(0),
+			Node::new(1, 1).with_seed(1).with_action(NodeAction::Bootstrap(0, 0)),
+		];
+		let mut transports: Vec<MockTransport> = (0..2).map(|net_addr| MockTransport { net_addr, wire: wire.clone() }).collect();
+		for _ in 0..100 {
+			for (node, transport) in nodes.iter_mut().zip(transports.iter_mut()) {
+				tick_over(node, transport);
+			}
+		}
+		assert_eq!(nodes[0].session_kind(&1), Some(SessionKind::Direct));
+		assert_eq!(nodes[1].session_kind(&0), Some(SessionKind::Direct));
+	}
+}
@@ -1,28 +1,53 @@
 #[allow(unused_imports)]
 
-const TARGET_PEER_COUNT: usize = 10;
 // Amount of time to wait to connect to a peer who wants to ping
 // const WANT_PING_CONN_TIMEOUT: usize = 300;
 const MAX_REQUEST_PINGS: usize = 10;
+// Recenter known coordinates around origin once their centroid drifts further than this on any axis
+const COORD_RECENTER_THRESHOLD: i64 = 1 << 32;
+// Maximum number of relay clients tracked, least active clients are forgotten first
+const MAX_RELAY_CLIENTS: usize = 64;
+// Maximum number of NodeIDs resolved by a single CoordQuery
+pub const MAX_COORD_QUERY: usize = 32;
+// Number of remembered remotes reconnected to at once after losing every session
+const RECONNECT_ATTEMPTS: usize = 3;
+// Maximum multiple of `reconnect_interval` waited between reconnect attempts while isolated
+const MAX_RECONNECT_BACKOFF: usize = 16;
+// Maximum multiple of `handshake_retransmit_interval` waited between handshake retransmissions
+const MAX_HANDSHAKE_BACKOFF: usize = 8;
+// Number of SessionIDs replaced by duplicate sessions remembered so their late packets can be ignored
+const MAX_RETIRED_SESSIONS: usize = 16;
+// Upper bound on how many outgoing packets are preallocated each tick
+const MAX_OUTGOING_HINT: usize = 256;
 
 use std::any::Any;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
 
+pub mod config;
 mod packet;
+pub mod policy;
 mod remote;
 mod session;
 pub mod types;
+#[cfg(test)]
+mod tests;
 
 use nalgebra::{Point, Vector2};
-pub use packet::{NodeEncryption, NodePacket, TraversedPacket};
-use remote::{RemoteNode, RemoteNodeError};
-use session::{RemoteSession, SessionError, SessionType};
-pub use types::{NodeID, RouteCoord, RouteScalar, SessionID};
+pub use config::{CoordConsensus, CoordInit, Encryption, NodeConfig, PeerSelection};
+pub use packet::{ErrorCode, NodeEncryption, NodePacket, PathProbe, TraversedPacket};
+pub use policy::{DefaultPeerPolicy, PeerPolicy};
+pub use remote::RemoteNode;
+use remote::RemoteNodeError;
+use session::{PeerStatus, RemoteSession, SessionError, SessionTracker, SessionType};
+pub use session::{TrackerSnapshot, TrafficStats};
+pub use types::{DhtKey, NodeID, RouteCoord, RouteCoordExt, RouteScalar, RouteScalarExt, SessionID, SessionKind, UNREACHABLE};
 
 use crate::internet::{CustomNode, NetAddr, NetSimPacket, NetSimPacketVec, NetSimRequest};
 
 use bimap::BiHashMap;
 use petgraph::graphmap::DiGraphMap;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
 use slotmap::SlotMap;
 use smallvec::SmallVec;
 
@@ -72,6 +97,10 @@ impl NodeActionCondition {
 pub enum NodeAction {
 	/// Bootstrap this node onto a specific other network node, starts the self-organization process
 	Bootstrap(NodeID, NetAddr),
+	/// Bootstrap off of whichever of several seed nodes connects first
+	BootstrapAny(Vec<(NodeID, NetAddr)>),
+	/// Wait for one of the seeds to connect and cancel the others, gives up at the passed tick
+	ResolveBootstrap(Vec<NodeID>, usize),
 	/// Initiate Handshake with remote NodeID, NetAddr and initial packets
 	Connect(NodeID, SessionType, Vec<NodePacket>),
 	/* /// Ping a node
@@ -86,12 +115,32 @@ pub enum NodeAction {
 	CalcRouteCoord,
 	/// Exchange Info with another node
 	ExchangeInformation(NodeID),
+	/// Tell a remote that this node is closing its session and close it
+	Disconnect(NodeID),
+	/// Stop peering with a remote in both directions but keep the session
+	Bye(NodeID),
+	/// Ask an intermediate node (first NodeID) to introduce this node to a target node (second NodeID) so the target connects to us
+	RequestIntroduction(NodeID, NodeID),
+	/// Ask a remote to send an unsolicited packet back to check if this node is reachable
+	TestReachability(NodeID),
+	/// Mark node as unreachable if reachability probe with this nonce was not answered
+	CheckReachability(u64),
 	/// Organize and set/unset known nodes as peers for Routing
 	CalculatePeers,
 	/// Sends a packet out onto the network for a specific recipient
 	Notify(NodeID, u64),
+	/// Challenge a remote to restate its RouteCoord so it can be checked against measured distance
+	ChallengeCoord(NodeID),
 	/// Send DHT request for Route Coordinate
 	RequestRouteCoord(NodeID),
+	/// Ask a remote this node has a session with (first NodeID) for the coordinates of many nodes in one packet
+	QueryCoords(NodeID, Vec<NodeID>),
+	/// Ask the region sharded DHT for up to `usize` nodes with coordinates near a RouteCoord
+	RequestRegion(RouteCoord, usize),
+	/// Share a sample of known Route Coordinates with peers
+	GossipCoords,
+	/// Remove route_map nodes that can't be reached from this node, and remotes that are neither reachable nor connected
+	PruneRoutes,
 	/// Establish Traversed Session with remote NodeID
 	/// Looks up remote node's RouteCoord on DHT and enables Traversed Session
 	ConnectTraversed(NodeID, Vec<NodePacket>),
@@ -100,19 +149,79 @@ pub enum NodeAction {
 	/// * `usize`: Number of intermediate nodes to route through
 	/// * `f64`: Random intermediate offset (high offset is more anonymous but less efficient, very high offset is random routing strategy)
 	ConnectRouted(NodeID, usize),
+	/// Wait for a ConnectRouted to a NodeID to resolve and emit NodeEvent::RoutedConnect, gives up at the passed tick
+	/// * `usize`: Number of hops the routed session takes
+	ResolveRoutedConnect(NodeID, usize, usize),
+	/// Measure the round trip latency of the routed path to a coordinate, emits NodeEvent::PathProbe when the probe returns
+	ProbePath(RouteCoord),
+	/// Resend a direct Handshake if it is still pending, scheduled by `connect`
+	/// * `usize`: Number of times the handshake has been retransmitted so far
+	RetransmitHandshake(NodeID, SessionID, usize),
 	/// Send specific packet to node
 	SendData(NodeID, Vec<u8>),
+	/// Send Application packet of a category to node
+	SendApplication(NodeID, u16, Vec<u8>),
 	/// Establish a dynamic routed connection
 	// Route(NodeID, RouteCoord),
 	/// Condition for a condition to be fulfilled before running imbedded Action
 	Condition(NodeActionCondition, Box<NodeAction>),
+	/// Run embedded action up to `remaining` more times, `interval` ticks apart, until it succeeds
+	/// The action succeeds if it completes without error and, if it has one, its success condition is satisfied
+	Retry { remaining: usize, interval: usize, action: Box<NodeAction> },
+	/// Run steps in order, each only once the previous one has completed (is no longer re-queued).
+	/// An error in any step aborts the rest of the sequence
+	Sequence(Vec<NodeAction>),
 }
 impl NodeAction {
 	pub fn gen_condition(self, condition: NodeActionCondition) -> NodeAction {
 		NodeAction::Condition(condition, Box::new(self))
 	}
+	pub fn gen_retry(self, attempts: usize, interval: usize) -> NodeAction {
+		NodeAction::Retry { remaining: attempts, interval, action: Box::new(self) }
+	}
+	/// Remotes this action (or a condition or step nested in it) will act on, they must not be pruned while it is queued
+	fn referenced_nodes(&self, nodes: &mut HashSet<NodeID>) {
+		match self {
+			NodeAction::Bootstrap(node_id, _) | NodeAction::Connect(node_id, ..) | NodeAction::UpdateRemote(node_id, ..)
+				| NodeAction::RequestPeers(node_id, _) | NodeAction::ExchangeInformation(node_id) | NodeAction::Disconnect(node_id)
+				| NodeAction::Bye(node_id) | NodeAction::TestReachability(node_id) | NodeAction::Notify(node_id, _)
+				| NodeAction::ChallengeCoord(node_id) | NodeAction::RequestRouteCoord(node_id) | NodeAction::ConnectTraversed(node_id, _)
+				| NodeAction::ConnectRouted(node_id, _) | NodeAction::ResolveRoutedConnect(node_id, ..) | NodeAction::RetransmitHandshake(node_id, ..)
+				| NodeAction::SendData(node_id, _) | NodeAction::SendApplication(node_id, ..) => { nodes.insert(*node_id); }
+			NodeAction::BootstrapAny(seeds) => nodes.extend(seeds.iter().map(|(node_id, _)| *node_id)),
+			NodeAction::ResolveBootstrap(node_ids, _) => nodes.extend(node_ids.iter().cloned()),
+			NodeAction::RequestIntroduction(intermediate, target) => { nodes.insert(*intermediate); nodes.insert(*target); }
+			NodeAction::QueryCoords(node_id, node_ids) => { nodes.insert(*node_id); nodes.extend(node_ids.iter().cloned()); }
+			NodeAction::Condition(condition, action) => {
+				if let NodeActionCondition::Session(node_id) | NodeActionCondition::RemoteRouteCoord(node_id) = condition { nodes.insert(*node_id); }
+				action.referenced_nodes(nodes);
+			}
+			NodeAction::Retry { action, .. } => action.referenced_nodes(nodes),
+			NodeAction::Sequence(steps) => steps.iter().for_each(|step| step.referenced_nodes(nodes)),
+			NodeAction::CalcRouteCoord | NodeAction::CheckReachability(_) | NodeAction::CalculatePeers | NodeAction::GossipCoords
+				| NodeAction::PruneRoutes | NodeAction::ProbePath(_) | NodeAction::RequestRegion(..) => {}
+		}
+	}
+	/// Condition that is satisfied once this action has had its intended effect, if it can be detected
+	pub fn success_condition(&self) -> Option<NodeActionCondition> {
+		match self {
+			NodeAction::Bootstrap(node_id, _) | NodeAction::Connect(node_id, ..) | NodeAction::ConnectTraversed(node_id, _) => Some(NodeActionCondition::Session(*node_id)),
+			NodeAction::RequestRouteCoord(node_id) => Some(NodeActionCondition::RemoteRouteCoord(*node_id)),
+			_ => None,
+		}
+	}
 }
 type ActionVec = SmallVec<[NodeAction; 8]>;
+/// Handles Application packets of a registered category, called with the sender and packet data. Returning Some sends a response back
+pub type PacketHandler = Box<dyn FnMut(NodeID, Vec<u8>) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Edge of the route_map, distance is blended with new measurements over time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteEdge {
+	pub dist: RouteScalar,
+	/// Tick at which this edge was last measured
+	pub last_updated: usize,
+}
 new_key_type! { pub struct NodeIdx; }
 
 #[derive(Error, Debug)]
@@ -146,6 +255,21 @@ pub enum NodeError {
 	#[error("Invalid SessionID: {session_id:?}")]
 	InvalidSessionID { session_id: SessionID },
 
+	#[error("Packet was encrypted with {received:?} but this node expects {expected:?}")]
+	EncryptionMismatch { expected: Encryption, received: Encryption },
+	#[error("Packet has unknown encryption tag: {tag:?}")]
+	UnknownEncryption { tag: Option<u8> },
+	#[error("Packet carries {received:?} dimensional coordinates but this node uses {expected}")]
+	CoordDimensionMismatch { expected: u8, received: Option<u8> },
+	#[error("Received Session packet for SessionID({session_id}) before its handshake completed")]
+	SessionKeyNotEstablished { session_id: SessionID },
+	#[error("Invalid config: {reason}")]
+	InvalidConfig { reason: &'static str },
+	#[error("Packet nested {depth} levels deep, maximum is {max}")]
+	PacketNestingTooDeep { depth: usize, max: usize },
+	#[error("Refusing to relay packet to {destination}, it is further than {radius} from this node")]
+	OutsideRelayRadius { destination: RouteCoord, radius: f64 },
+
 	#[error("Triggered RemoteNodeError")]
 	RemoteNodeError(#[from] RemoteNodeError),
 	#[error("Remote Session Error")]
@@ -159,6 +283,96 @@ impl NodeError {
 	pub fn anyhow(self) -> NodeError {
 		NodeError::Other(anyhow::Error::new(self))
 	}
+	/// Code reported to the remote whose packet caused this error
+	pub fn error_code(&self) -> ErrorCode {
+		match self {
+			NodeError::DecodeError(_) | NodeError::EncryptionMismatch { .. } | NodeError::UnknownEncryption { .. }
+				| NodeError::CoordDimensionMismatch { .. } | NodeError::PacketNestingTooDeep { .. } => ErrorCode::Malformed,
+			NodeError::UnknownSession { .. } | NodeError::InvalidSessionID { .. } | NodeError::SessionKeyNotEstablished { .. } => ErrorCode::UnknownSession,
+			NodeError::OutsideRelayRadius { .. } => ErrorCode::Refused,
+			_ => ErrorCode::Internal,
+		}
+	}
+}
+/// Events that are surfaced to the application using this node
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeEvent {
+	/// Received a NodeEncryption::Notify addressed to this node
+	Notify { sender: NodeID, data: u64 },
+	/// A Traverse packet sent by this node was acknowledged by its destination
+	/// * `latency`: Ticks between sending the packet and receiving the acknowledgement
+	TraversalAck { acknowledger: NodeID, latency: usize },
+	/// Emitted once, the first time this node has a route coordinate and at least one peer
+	Ready,
+	/// A ConnectRouted finished, with the number of hops of the established session or why it failed
+	RoutedConnect { target: NodeID, result: Result<usize, RoutedConnectFailure> },
+	/// A DHT lookup of a node's coordinate went unanswered `config.dht_attempts` times, actions waiting on its coordinate were dropped
+	RouteCoordLookupFailed { node_id: NodeID },
+	/// A remote rejected a packet sent by this node
+	ProtocolError { sender: NodeID, code: ErrorCode, context: String },
+	/// A probe sent with NodeAction::ProbePath returned
+	/// * `rtt`: Ticks between sending the probe and it returning
+	/// * `hops`: Number of hops taken there and back
+	PathProbe { destination: RouteCoord, rtt: usize, hops: usize },
+}
+
+/// Reason a ConnectRouted failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutedConnectFailure {
+	/// The target's route coordinate could not be found before the timeout
+	NoRouteCoord,
+	/// No peers to route through towards the target
+	NoPath,
+	/// The handshake was sent but the session was not established before the timeout
+	Timeout,
+}
+
+/// Number of active sessions of each type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SessionCounts {
+	pub direct: usize,
+	pub traversed: usize,
+	pub routed: usize,
+}
+impl SessionCounts {
+	pub fn total(&self) -> usize { self.direct + self.traversed + self.routed }
+}
+
+/// Summary of a node's state for monitoring
+#[derive(Debug, Default, Clone)]
+pub struct NodeStats {
+	pub session_counts: SessionCounts,
+	pub peer_count: usize,
+	pub known_remotes: usize,
+	/// Average number of ticks taken to establish the currently active sessions
+	pub avg_setup_latency: Option<f64>,
+	/// See `Node::peer_churn_rate`
+	pub peer_churn_rate: f64,
+}
+
+/// Path a Traverse packet would take, computed from this node's local knowledge
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedRoute {
+	/// NodeIDs visited, starting with this node
+	pub path: Vec<NodeID>,
+	/// True if the last node in path sits at the destination coordinate, false if routing hit a dead end or max_hops
+	pub reached: bool,
+}
+
+/// Record of everything that went wrong during a single tick
+#[derive(Debug, Default, Clone)]
+pub struct TickDiagnostics {
+	/// InternetPackets that could not be parsed and were dropped
+	pub dropped_packets: usize,
+	/// NodePackets that produced an error while being handled
+	pub errored_packets: usize,
+	/// Actions that errored and the error they returned
+	pub errored_actions: Vec<(NodeAction, String)>,
+}
+impl TickDiagnostics {
+	pub fn is_clean(&self) -> bool {
+		self.dropped_packets == 0 && self.errored_packets == 0 && self.errored_actions.is_empty()
+	}
 }
 
 #[derive(Derivative, Serialize, Deserialize)]
@@ -171,26 +385,121 @@ pub struct Node {
 	#[derivative(Debug = "ignore")]
 	deus_ex_data: Option<RouteCoord>,
 	pub is_public: bool, // Does this node publish it's RouteCoord to the DHT?
+	anchor: bool, // Is this node's RouteCoord pinned by `set_anchor`?
+	reachable: Option<bool>, // Result of bootstrap reachability test, None if not yet tested
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	reachability_probe: Option<u64>, // Nonce of pending reachability probe
+	#[derivative(Debug = "ignore")]
+	route_coord_updated: Option<usize>, // Tick this node's route coordinate was last changed
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	route_anchor_count: usize, // Number of usable anchors when this node's route coordinate was last calculated
 	#[derivative(Debug = "ignore")]
 	public_route: Option<RouteCoord>,
+	#[derivative(Debug = "ignore")]
+	last_published: Option<usize>, // Tick public_route was last written to the DHT
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	recent_coords: VecDeque<RouteCoord>, // Last `config.oscillation_window` calculated coordinates, used to detect oscillation
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	coordinate_history: Vec<(usize, RouteCoord)>, // (tick, coordinate) of the last `config.coordinate_history_size` committed coordinates, oldest first
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	coord_frozen_until: Option<usize>, // Tick until which this node's coordinate is frozen after oscillating
 	pub ticks: usize, // Amount of time passed since startup of this node
+	pub config: NodeConfig,
 
 	pub remotes: SlotMap<NodeIdx, RemoteNode>, // ECS-type data structure that stores all nodes
 	pub ids: BiHashMap<NodeID, NodeIdx>,
+	#[derivative(Debug = "ignore")]
+	tombstones: HashMap<NodeID, usize>, // Recently disconnected nodes mapped to the tick their cooldown ends
+	#[derivative(Debug = "ignore")]
+	pinned_peers: HashSet<NodeID>, // Remotes that are always kept as peers while they have a direct session
+	#[derivative(Debug = "ignore")]
+	released_peers: HashSet<NodeID>, // Remotes released with Bye, kept out of peer selection until `reenable_peer`
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	prune_requested: bool, // Set by PruneRoutes, routes are pruned after the tick's actions have run
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	retired_sessions: VecDeque<SessionID>, // Duplicate sessions dropped by `install_session`, packets on them are ignored
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	relay_clients: HashMap<NodeID, usize>, // Previous hops of Traverse packets forwarded by this node, mapped to number of packets forwarded
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	peer_churn: VecDeque<(usize, usize)>, // (tick, number of peer_list additions and removals) for each CalculatePeers that changed peers within `config.churn_window`
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	peer_changes: HashMap<NodeIdx, usize>, // Remotes whose peer_list membership differs from what was last notified, mapped to the tick it changed
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	pending_dht: HashMap<NodeID, (usize, usize)>, // Unanswered DHT reads mapped to (tick they time out, attempts so far)
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	pending_probes: HashMap<u64, RouteCoord>, // Path probes sent by this node awaiting their return, mapped to the probed coordinate
 
 	pub sessions: BiHashMap<SessionID, NodeIdx>, // Each SessionID links to a unique RemoteNode
-	pub direct_sorted: BTreeMap<u64, NodeIdx>, // All nodes that have been tested, sorted by lowest value
+	pub direct_sorted: BTreeMap<(RouteScalar, NodeID), NodeIdx>, // All nodes that have been tested, sorted by lowest distance then NodeID so equal distances don't collide
 
-	pub peer_list: BiHashMap<NodeIdx, RouteCoord>, // Used for routing and peer management, peer count should be no more than TARGET_PEER_COUNT
+	pub peer_list: BiHashMap<NodeIdx, RouteCoord>, // Used for routing and peer management, peer count should be no more than config.target_peer_count
 	#[derivative(Debug = "ignore")]
 	#[serde(skip)]
-	pub route_map: DiGraphMap<NodeID, u64>, // Bi-directional graph of all locally known nodes and the estimated distances between them
+	pub route_map: DiGraphMap<NodeID, RouteEdge>, // Bi-directional graph of all locally known nodes and the estimated distances between them
 	#[serde(skip)]
 	pub action_list: ActionVec, // Actions will wait here until NodeID session is established
+	#[serde(skip)]
+	events: Vec<NodeEvent>, // Events waiting to be handled by the application
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	ready_emitted: bool, // NodeEvent::Ready has already been emitted
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	diagnostics: TickDiagnostics, // Errors that occured during the last tick
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	next_reconnect: usize, // Earliest tick the isolation watchdog may reconnect again
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	reconnect_backoff: usize, // Current multiple of `config.reconnect_interval` between reconnect attempts, 0 while connected
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	strict_error: Option<NodeError>, // First error of the current tick when `config.strict` is set, returned by `try_tick`
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	action_buffer: ActionVec, // Scratch buffers reused between ticks
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	new_action_buffer: ActionVec,
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	outgoing_hint: usize, // Number of packets sent last tick, used to preallocate outgoing
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	outbox: PacketVec, // Outgoing packets buffered by `tick_buffered` until `drain_outgoing` is called, already passed through drop_filter
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	queued_outgoing: PacketVec, // Packets queued outside of tick (e.g. by reset_coordinate), sent and filtered with the next tick's outgoing packets
+	#[derivative(Debug = "ignore", Default(value = "Mutex::new(SmallRng::from_entropy())"))]
+	#[serde(skip, default = "default_rng")]
+	rng: Mutex<SmallRng>, // Source of SessionIDs, nonces and random routing choices, seed with `with_seed` for reproducible runs
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	drop_filter: Option<Box<dyn Fn(&InternetPacket) -> bool + Send + Sync>>, // Incoming and outgoing packets matching this are dropped, for chaos testing
+	#[derivative(Debug = "ignore")]
+	#[serde(skip)]
+	packet_handlers: HashMap<u16, PacketHandler>, // Application packet handlers by category
+	#[derivative(Debug = "ignore", Default(value = "policy::default_peer_policy()"))]
+	#[serde(skip, default = "policy::default_peer_policy")]
+	peer_policy: Box<dyn PeerPolicy>, // Decides which direct remotes are viable peers
 }
+fn default_rng() -> Mutex<SmallRng> { Mutex::new(SmallRng::from_entropy()) }
 impl CustomNode for Node {
 	type CustomNodeAction = NodeAction;
 	type CustomNodeUUID = NodeID;
+	type Packet = InternetPacket;
 	fn net_addr(&self) -> NetAddr {
 		self.net_addr
 	}
@@ -198,67 +507,115 @@ impl CustomNode for Node {
 		self.node_id
 	}
 	fn tick(&mut self, incoming: PacketVec) -> PacketVec {
-		let mut outgoing = PacketVec::new();
+		let mut outgoing = PacketVec::with_capacity(usize::min(self.outgoing_hint, MAX_OUTGOING_HINT));
+		outgoing.extend(self.queued_outgoing.drain(..));
+		self.diagnostics = TickDiagnostics::default();
+		self.strict_error = None;
 
 		// Parse Incoming Packets
 		for packet in incoming {
+			// Strict mode stops handling packets at the first error
+			if self.strict_error.is_some() { break }
+			if self.should_drop(&packet) { continue }
 			let (src_addr, dest_addr) = (packet.src_addr, packet.dest_addr);
 			match self.parse_packet(packet, &mut outgoing) {
 				Ok(Some((return_node_idx, node_packet))) => {
 					if let Err(err) =
-						self.parse_node_packet(return_node_idx, node_packet, &mut outgoing)
+						self.parse_node_packet(return_node_idx, node_packet, &mut outgoing, 0)
 					{
+						self.diagnostics.errored_packets += 1;
 						log::error!(
 							"Error in parsing NodePacket from NodeID({}) to NodeID({}): {:?}",
 							self.remote(return_node_idx).unwrap().node_id,
 							self.node_id,
 							err
 						);
+						self.report_error(return_node_idx, &err, &mut outgoing);
+						self.record_strict_error(err);
 					}
 				}
 				Ok(None) => {}
 				Err(err) => {
+					self.diagnostics.dropped_packets += 1;
 					log::error!(
 						"Error in parsing InternetPacket from NetAddr({}) to NetAddr({}): {:?}",
 						src_addr,
 						dest_addr,
-						anyhow::Error::new(err)
+						err
 					);
 					log::error!("Erroring Node: {}", self);
+					self.record_strict_error(err);
 				}
 			}
 		}
 
-		let mut new_actions = ActionVec::new(); // Create buffer for new actions
-		let aq = std::mem::replace(&mut self.action_list, Default::default()); // Move actions out of action_list
-																	   // Execute and collect actions back into action_list
-		self.action_list = aq
-			.into_iter()
-			.filter_map(|action| {
-				let action_clone = action.clone();
-				self.parse_action(action, &mut outgoing, &mut new_actions)
-					.unwrap_or_else(|err| {
-						log::error!(
-							"NodeID({}), Action {:?} errored: {:?}",
-							self.node_id,
-							action_clone,
-							err
-						);
-						None
-					})
-			})
-			.collect();
+		self.check_isolation();
+
+		// Move actions out of action_list, buffers are swapped rather than reallocated every tick
+		let mut queued_actions = std::mem::replace(&mut self.action_list, std::mem::take(&mut self.action_buffer));
+		let mut new_actions = std::mem::take(&mut self.new_action_buffer); // Create buffer for new actions
+		// Execute and collect actions back into action_list
+		for action in queued_actions.drain(..) {
+			// Strict mode leaves remaining actions queued after the first error
+			if self.strict_error.is_some() { self.action_list.push(action); continue }
+			let action_clone = action.clone();
+			match self.parse_action(action, &mut outgoing, &mut new_actions) {
+				Ok(Some(action)) => self.action_list.push(action),
+				Ok(None) => {}
+				Err(err) => {
+					log::error!(
+						"NodeID({}), Action {:?} errored: {:?}",
+						self.node_id,
+						action_clone,
+						err
+					);
+					self.diagnostics.errored_actions.push((action_clone, err.to_string()));
+					self.record_strict_error(err);
+				}
+			}
+		}
 		self.action_list.append(&mut new_actions); // Record new actions
+		// Pruning waits until every action is back in action_list so remotes they refer to are kept
+		if std::mem::take(&mut self.prune_requested) { self.prune_routes() }
+		self.action_buffer = queued_actions;
+		self.new_action_buffer = new_actions;
+		self.retransmit_unacked(&mut outgoing);
+		self.check_dht_timeouts(&mut outgoing);
+		if self.drop_filter.is_some() {
+			outgoing.retain(|packet| !self.should_drop(packet));
+		}
+		self.outgoing_hint = outgoing.len();
+
+		if self.config.sweep_interval != 0 && self.ticks % self.config.sweep_interval == 0 {
+			self.sweep();
+		}
+		if !self.ready_emitted && self.is_ready() {
+			self.ready_emitted = true;
+			self.events.push(NodeEvent::Ready);
+		}
 
 		self.ticks += 1;
 		outgoing
 	}
 	fn action(&mut self, action: NodeAction) {
+		// Coalesce UpdateRemote actions for the same remote, only the latest values matter
+		if let NodeAction::UpdateRemote(node_id, ..) = action {
+			let pending = self.action_list.iter_mut().find(|pending| {
+				matches!(pending, NodeAction::UpdateRemote(pending_id, ..) if *pending_id == node_id)
+			});
+			if let Some(pending) = pending {
+				*pending = action;
+				return;
+			}
+		}
 		self.action_list.push(action);
 	}
 	fn as_any(&self) -> &dyn Any {
 		self
 	}
+	fn offset_clock(&mut self, ticks: usize) {
+		self.ticks += ticks;
+	}
 	fn set_deus_ex_data(&mut self, data: Option<RouteCoord>) {
 		self.deus_ex_data = data;
 	}
@@ -273,10 +630,301 @@ impl Node {
 			..Default::default()
 		}
 	}
+	/// Seed this node's random number generator so generated SessionIDs and nonces are reproducible
+	pub fn with_seed(mut self, seed: u64) -> Self {
+		self.rng = Mutex::new(SmallRng::seed_from_u64(seed));
+		self
+	}
+	/// Register a handler for Application packets of `category`, replacing any previous handler
+	pub fn register_handler(&mut self, category: u16, handler: impl FnMut(NodeID, Vec<u8>) -> Option<Vec<u8>> + Send + Sync + 'static) {
+		self.packet_handlers.insert(category, Box::new(handler));
+	}
+	pub fn unregister_handler(&mut self, category: u16) {
+		self.packet_handlers.remove(&category);
+	}
+	/// Drop any incoming or outgoing packet for which `filter` returns true
+	pub fn set_drop_filter(&mut self, filter: impl Fn(&InternetPacket) -> bool + Send + Sync + 'static) {
+		self.drop_filter = Some(Box::new(filter));
+	}
+	pub fn clear_drop_filter(&mut self) {
+		self.drop_filter = None;
+	}
+	fn should_drop(&self, packet: &InternetPacket) -> bool {
+		let drop = self.drop_filter.as_ref().map_or(false, |filter| filter(packet));
+		if drop {
+			log::trace!("[{: >6}] NodeID({}) drop_filter dropped packet {} -> {}", self.ticks, self.node_id, packet.src_addr, packet.dest_addr);
+		}
+		drop
+	}
+	/// Replace the strategy used by CalculatePeers to decide which remotes are viable peers
+	pub fn with_peer_policy(mut self, policy: impl PeerPolicy + 'static) -> Self {
+		self.peer_policy = Box::new(policy);
+		self
+	}
 	pub fn with_action(mut self, action: NodeAction) -> Self {
 		self.action_list.push(action);
 		self
 	}
+	/// Tick this node, buffering outgoing packets internally instead of returning them, for drivers that schedule sending themselves
+	pub fn tick_buffered(&mut self, incoming: PacketVec) {
+		let outgoing = self.tick(incoming);
+		self.outbox.extend(outgoing);
+	}
+	/// Take all packets buffered by `tick_buffered` since the last call
+	pub fn drain_outgoing(&mut self) -> PacketVec {
+		std::mem::take(&mut self.outbox)
+	}
+	/// Parse a single packet and return the packets sent in response, without advancing time or running queued actions
+	pub fn handle_packet(&mut self, packet: InternetPacket) -> Result<PacketVec, NodeError> {
+		let mut outgoing = PacketVec::new();
+		if let Some((return_node_idx, node_packet)) = self.parse_packet(packet, &mut outgoing)? {
+			self.parse_node_packet(return_node_idx, node_packet, &mut outgoing, 0)?;
+		}
+		Ok(outgoing)
+	}
+	/// Verify that the node's lookup maps agree with each other, returns a description of the first inconsistency found
+	#[cfg(debug_assertions)]
+	pub fn check_invariants(&self) -> Result<(), String> {
+		for (node_id, &node_idx) in self.ids.iter() {
+			let remote = self.remotes.get(node_idx).ok_or_else(|| format!("ids entry NodeID({}) points to missing remote {:?}", node_id, node_idx))?;
+			if remote.node_id != *node_id {
+				return Err(format!("ids entry NodeID({}) points to remote with NodeID({})", node_id, remote.node_id));
+			}
+		}
+		for (session_id, &node_idx) in self.sessions.iter() {
+			let remote = self.remotes.get(node_idx).ok_or_else(|| format!("SessionID({}) points to missing remote {:?}", session_id, node_idx))?;
+			match &remote.session {
+				Some(session) if session.session_id == *session_id => {}
+				Some(session) => return Err(format!("SessionID({}) points to NodeID({}) which has SessionID({})", session_id, remote.node_id, session.session_id)),
+				None => return Err(format!("SessionID({}) points to NodeID({}) which has no session", session_id, remote.node_id)),
+			}
+		}
+		for (dist, node_idx) in self.direct_sorted.iter() {
+			if !self.sessions.contains_right(node_idx) {
+				return Err(format!("direct_sorted entry {:?} -> {:?} has no session", dist, node_idx));
+			}
+		}
+		for (node_idx, route_coord) in self.peer_list.iter() {
+			let remote = self.remotes.get(*node_idx).ok_or_else(|| format!("peer_list entry {:?} points to missing remote", node_idx))?;
+			if !remote.session.as_ref().map_or(false, |s| s.direct().is_ok()) {
+				return Err(format!("peer NodeID({}) at {} has no direct session", remote.node_id, route_coord));
+			}
+		}
+		Ok(())
+	}
+	/// True if this node has a route coordinate and at least one peer, and so can establish routed connections
+	pub fn is_ready(&self) -> bool {
+		self.route_coord.is_some() && !self.peer_list.is_empty()
+	}
+	/// False if this node failed its reachability self-test and can't accept unsolicited connections
+	pub fn is_reachable(&self) -> bool {
+		self.reachable != Some(false)
+	}
+	/// False while this node's coordinate is frozen because it was oscillating between recalculations
+	pub fn is_coordinate_stable(&self) -> bool {
+		self.coord_frozen_until.map_or(true, |until| self.ticks >= until) && !self.is_oscillating()
+	}
+	/// True if every recent recalculation moved the coordinate back in the direction it came from
+	fn is_oscillating(&self) -> bool {
+		use itertools::Itertools;
+		let window = self.config.oscillation_window;
+		if window < 3 || self.recent_coords.len() < window { return false }
+		let moves = self.recent_coords.iter().tuple_windows().map(|(a, b)| b.as_f64() - a.as_f64()).collect::<Vec<Vector2<f64>>>();
+		moves.iter().tuple_windows().all(|(a, b)| a.dot(b) < 0.0)
+	}
+	fn set_self_route_coord(&mut self, route_coord: RouteCoord) {
+		self.route_coord = Some(route_coord);
+		self.route_coord_updated = Some(self.ticks);
+		if self.config.coordinate_history_size != 0 {
+			self.coordinate_history.push((self.ticks, route_coord));
+			self.trim_coordinate_history();
+		}
+	}
+	/// (tick, coordinate) of each coordinate this node committed to, oldest first, bounded by `config.coordinate_history_size`
+	pub fn coordinate_history(&self) -> &[(usize, RouteCoord)] {
+		&self.coordinate_history
+	}
+	fn trim_coordinate_history(&mut self) {
+		let excess = self.coordinate_history.len().saturating_sub(self.config.coordinate_history_size);
+		self.coordinate_history.drain(..excess);
+	}
+	/// Region based DHT key for a coordinate, the DHT shards entries and answers `RequestRegion` lookups by this key
+	pub fn coordinate_to_dht_key(coord: RouteCoord) -> DhtKey {
+		types::coordinate_to_dht_key(coord)
+	}
+	/// Pick one coordinate out of possibly conflicting `(RouteCoord, age in ticks)` replicas according to `config.coord_consensus`.
+	/// NetSim's DHT keeps a single copy of each coordinate, this is for transports with a replicated DHT
+	pub fn route_coord_consensus(&self, candidates: &[(RouteCoord, usize)]) -> Option<RouteCoord> {
+		match self.config.coord_consensus {
+			CoordConsensus::Freshest => candidates.iter().min_by_key(|(_, age)| *age).map(|(route_coord, _)| *route_coord),
+			CoordConsensus::Median => {
+				if candidates.is_empty() { return None }
+				// Per-axis median so a single outlier replica can't drag the result
+				let median = |mut values: Vec<i64>| {
+					values.sort_unstable();
+					let mid = values.len() / 2;
+					if values.len() % 2 == 0 { ((values[mid - 1] as i128 + values[mid] as i128) / 2) as i64 } else { values[mid] }
+				};
+				let x = median(candidates.iter().map(|(c, _)| c.x).collect());
+				let y = median(candidates.iter().map(|(c, _)| c.y).collect());
+				Some(RouteCoord::new(x, y))
+			}
+		}
+	}
+	/// Number of ticks since a node's route coordinate (or this node's own) was last updated, None if it has none
+	pub fn coord_age(&self, node_id: NodeID) -> Option<usize> {
+		let updated = if node_id == self.node_id {
+			self.route_coord_updated?
+		} else {
+			self.remote(self.index_by_node_id(&node_id).ok()?).ok()?.coord_updated?
+		};
+		Some(self.ticks.saturating_sub(updated))
+	}
+	/// Measured distance to a remote in milliseconds according to `config.tick_duration`, None if there is no measurement
+	pub fn dist_ms(&self, node_id: &NodeID) -> Option<f64> {
+		let dist = self.remote(self.index_by_node_id(node_id).ok()?).ok()?.session().ok()?.dist();
+		if !dist.is_known() { return None }
+		Some(self.config.ticks_to_ms(dist as f64))
+	}
+	/// Minimum and maximum corners of the box containing every known coordinate (this node's and remotes'), None if no coordinates are known
+	pub fn coordinate_extent(&self) -> Option<(RouteCoord, RouteCoord)> {
+		let mut coords = self.route_coord.iter().chain(self.remotes.values().filter_map(|r| r.route_coord.as_ref()));
+		let first = *coords.next()?;
+		Some(coords.fold((first, first), |(min, max), c| (min.inf(c), max.sup(c))))
+	}
+	/// Rough estimate of the number of nodes in the network, extrapolated from the density of known coordinates near this node over the known coordinate extent.
+	/// Falls back to the number of known nodes when there aren't enough coordinates to estimate density
+	pub fn estimated_network_size(&self) -> usize {
+		let known = self.remotes.len() + 1;
+		let self_route_coord = match self.route_coord { Some(coord) => coord, None => return known };
+		let mut dists = self.remotes.values()
+			.filter_map(|r| r.route_coord.map(|c| types::route_dist(&self_route_coord, &c)))
+			.collect::<Vec<f64>>();
+		if dists.len() < 3 { return known }
+		dists.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+		// Density within the radius containing the nearest k nodes
+		let k = usize::min(self.config.target_peer_count, dists.len());
+		let radius = dists[k - 1];
+		let (min, max) = match self.coordinate_extent() { Some(extent) => extent, None => return known };
+		let area = (max.x - min.x) as f64 * (max.y - min.y) as f64;
+		if radius <= 0.0 || area <= 0.0 { return known }
+		let density = k as f64 / (std::f64::consts::PI * radius * radius);
+		usize::max(known, (density * area).round() as usize)
+	}
+	/// Count active sessions by session type
+	pub fn session_counts(&self) -> SessionCounts {
+		let mut counts = SessionCounts::default();
+		for (_, &node_idx) in self.sessions.iter() {
+			if let Some(session) = self.remotes.get(node_idx).and_then(|r| r.session.as_ref()) {
+				match session.session_type.kind() {
+					SessionKind::Direct => counts.direct += 1,
+					SessionKind::Traversed => counts.traversed += 1,
+					SessionKind::Routed => counts.routed += 1,
+				}
+			}
+		}
+		counts
+	}
+	/// Traffic summed over every session this node currently has
+	pub fn traffic_summary(&self) -> TrafficStats {
+		let mut summary = TrafficStats::default();
+		for session in self.remotes.values().filter_map(|r| r.session().ok()) {
+			summary += session.traffic_stats();
+		}
+		summary
+	}
+	/// Peer additions and removals made by CalculatePeers per 1000 ticks, averaged over the last `config.churn_window` ticks
+	pub fn peer_churn_rate(&self) -> f64 {
+		let window = self.config.churn_window.min(self.ticks.max(1));
+		if window == 0 { return 0.0 }
+		let since = self.ticks.saturating_sub(window);
+		let changes = self.peer_churn.iter().filter(|(tick, _)| *tick >= since).map(|(_, changes)| changes).sum::<usize>();
+		changes as f64 * 1000.0 / window as f64
+	}
+	fn record_peer_churn(&mut self, changes: usize) {
+		let since = self.ticks.saturating_sub(self.config.churn_window);
+		while self.peer_churn.front().map_or(false, |(tick, _)| *tick < since) { self.peer_churn.pop_front(); }
+		if changes != 0 { self.peer_churn.push_back((self.ticks, changes)); }
+	}
+	pub fn stats(&self) -> NodeStats {
+		NodeStats {
+			session_counts: self.session_counts(),
+			peer_count: self.peer_list.len(),
+			known_remotes: self.remotes.len(),
+			peer_churn_rate: self.peer_churn_rate(),
+			avg_setup_latency: {
+				let latencies = self.remotes.values()
+					.filter_map(|r| r.session.as_ref()?.setup_latency())
+					.collect::<Vec<usize>>();
+				if latencies.is_empty() { None } else { Some(latencies.iter().sum::<usize>() as f64 / latencies.len() as f64) }
+			},
+		}
+	}
+	/// Difference between measured distance and coordinate-predicted distance for every remote with both a coordinate and a ping sample.
+	/// Large residuals show which links the embedding fits poorly
+	pub fn peer_residuals(&self) -> Vec<(NodeID, f64)> {
+		let self_route_coord = match self.route_coord { Some(coord) => coord, None => return Vec::new() };
+		self.remotes.values().filter_map(|remote| {
+			let route_coord = remote.route_coord?;
+			let tracker = &remote.session().ok()?.tracker;
+			if tracker.ping_count == 0 || !tracker.dist_avg.is_known() { return None }
+			let predicted = types::route_dist(&self_route_coord, &route_coord);
+			Some((remote.node_id, tracker.dist_avg as f64 - predicted))
+		}).collect()
+	}
+	/// Take all events that have occured since the last call
+	pub fn drain_events(&mut self) -> Vec<NodeEvent> {
+		std::mem::take(&mut self.events)
+	}
+	/// Events that have occured since the last drain_events call
+	pub fn events(&self) -> &[NodeEvent] {
+		&self.events
+	}
+	/// Tick this node like `CustomNode::tick` and return its outgoing packets.
+	/// With `config.strict` set, the first error of the tick is returned instead of only being logged, packets and actions after it are left unhandled
+	pub fn try_tick(&mut self, incoming: PacketVec) -> Result<PacketVec, NodeError> {
+		let outgoing = self.tick(incoming);
+		match self.strict_error.take() {
+			Some(err) => Err(err),
+			None => Ok(outgoing),
+		}
+	}
+	/// Tell a remote that a packet it sent was rejected, over the session the packet arrived on
+	fn report_error(&self, node_idx: NodeIdx, err: &NodeError, outgoing: &mut PacketVec) {
+		if !self.config.report_errors { return }
+		let result: Result<(), NodeError> = try {
+			let remote = self.remote(node_idx)?;
+			let encryption = NodeEncryption::Error { recipient: remote.node_id, sender: self.node_id, code: err.error_code(), context: err.to_string() };
+			outgoing.push(remote.session()?.gen_packet(encryption, self)?);
+		};
+		if let Err(report_err) = result {
+			log::debug!("NodeID({}) failed to report error to remote: {:?}", self.node_id, report_err);
+		}
+	}
+	fn record_strict_error(&mut self, err: NodeError) {
+		if self.config.strict && self.strict_error.is_none() {
+			self.strict_error = Some(err);
+		}
+	}
+	/// Packets dropped and actions errored during the most recent tick
+	pub fn last_tick_diagnostics(&self) -> &TickDiagnostics {
+		&self.diagnostics
+	}
+	/// Free the scratch buffers kept between ticks, the next tick allocates them again
+	pub fn release_buffers(&mut self) {
+		self.action_buffer = ActionVec::new();
+		self.new_action_buffer = ActionVec::new();
+		self.outgoing_hint = 0;
+	}
+	/// Bootstrap off of the first of multiple seed nodes to respond
+	pub fn bootstrap_from(self, seeds: &[(NodeID, NetAddr)]) -> Self {
+		self.with_action(NodeAction::BootstrapAny(seeds.to_vec()))
+	}
+	/// Actions waiting to be run next tick, including unresolved Condition actions
+	pub fn pending_actions(&self) -> &[NodeAction] {
+		&self.action_list
+	}
 
 	pub fn add_remote(&mut self, node_id: NodeID) -> Result<(NodeIdx, &mut RemoteNode), NodeError> {
 		let node_idx = if let Some(node_idx) = self.ids.get_by_left(&node_id) {
@@ -315,18 +963,113 @@ impl Node {
 			})
 	}
 
+	/// Returns what kind of session this node has with a remote, or None if there is no active session
+	pub fn session_kind(&self, node_id: &NodeID) -> Option<SessionKind> {
+		let remote = self.remote(self.index_by_node_id(node_id).ok()?).ok()?;
+		remote.session().ok().map(|session| session.session_type.kind())
+	}
+
+	fn gen_random<T>(&self) -> T where rand::distributions::Standard: rand::distributions::Distribution<T> {
+		self.rng.lock().expect("rng lock poisoned").gen()
+	}
 	pub fn find_closest_peer(&self, remote_route_coord: &RouteCoord) -> Result<NodeIdx, NodeError> {
-		let min_peer = self.peer_list.iter().min_by_key(|(_, &p)| {
-			let diff = p - *remote_route_coord;
-			diff.dot(&diff)
-			//println!("Dist from {:?}: {}: {}", self.node_id, self.remote(**id).unwrap().node_id, d_sq);
-			//d_sq
-		});
-		min_peer
-			.map(|(&node, _)| node)
+		if let PeerSelection::WeightedRandom { k } = self.config.peer_selection {
+			if let Some(node_idx) = self.weighted_random_peer(remote_route_coord, k) { return Ok(node_idx) }
+		}
+		// Overflowing distances saturate so they compare as maximally distant
+		let min_peer = self.peer_list.iter()
+			.filter(|(&node_idx, _)| !self.is_loopback(node_idx))
+			.min_by_key(|(_, p)| types::route_dist_sq(p, remote_route_coord));
+		if let Some((&node, _)) = min_peer { return Ok(node) }
+
+		// Fall back to direct sessions before CalculatePeers has run, prefer closest known coordinate, then lowest latency
+		let mut direct = self.direct_sorted.values().cloned()
+			.filter(|&node_idx| self.remote(node_idx).map_or(false, |r| r.session_active()) && !self.is_loopback(node_idx));
+		direct.clone()
+			.filter_map(|node_idx| Some((node_idx, self.remote(node_idx).ok()?.route_coord?)))
+			.min_by_key(|(_, route_coord)| types::route_dist_sq(route_coord, remote_route_coord))
+			.map(|(node_idx, _)| node_idx)
+			.or_else(|| direct.next())
 			.ok_or(NodeError::InsufficientPeers { required: 1 })
 	}
 
+	/// Only republish to the DHT when the coordinate has moved meaningfully and the last write was long enough ago
+	fn should_publish(&self, route_coord: RouteCoord) -> bool {
+		match (self.public_route, self.last_published) {
+			(Some(public_route), Some(last_published)) => {
+				types::route_dist(&public_route, &route_coord) > self.config.dht_publish_threshold
+					&& self.ticks.saturating_sub(last_published) >= self.config.dht_publish_interval
+			}
+			_ => true,
+		}
+	}
+	/// True if sending to this remote would deliver the packet back to this node
+	fn is_loopback(&self, node_idx: NodeIdx) -> bool {
+		self.remote(node_idx).map_or(false, |remote| {
+			remote.node_id == self.node_id
+				|| remote.session().ok().and_then(|s| s.direct().ok()).map_or(false, |d| d.net_addr == self.net_addr)
+		})
+	}
+	/// Pick one of the `k` peers closest to `route_coord`, weighted by inverse distance
+	fn weighted_random_peer(&self, route_coord: &RouteCoord, k: usize) -> Option<NodeIdx> {
+		let mut closest = self.peer_list.iter()
+			.filter(|(&node_idx, _)| !self.is_loopback(node_idx))
+			.map(|(&node_idx, peer_coord)| (node_idx, types::route_dist(peer_coord, route_coord)))
+			.collect::<Vec<(NodeIdx, f64)>>();
+		closest.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+		closest.truncate(k.max(1));
+		let weights = closest.iter().map(|(_, dist)| 1.0 / (dist + 1.0)).collect::<Vec<f64>>();
+		let mut choice = self.gen_random::<f64>() * weights.iter().sum::<f64>();
+		for ((node_idx, _), weight) in closest.iter().zip(weights) {
+			if choice < weight { return Some(*node_idx) }
+			choice -= weight;
+		}
+		closest.last().map(|(node_idx, _)| *node_idx)
+	}
+	/// Lowest-cost path between two nodes in the local route_map, and its total distance.
+	/// Reflects this node's knowledge of the topology rather than how packets are actually routed
+	pub fn shortest_path(&self, from: NodeID, to: NodeID) -> Option<(Vec<NodeID>, u64)> {
+		if !self.route_map.contains_node(from) || !self.route_map.contains_node(to) { return None }
+		petgraph::algo::astar(&self.route_map, from, |node| node == to, |(_, _, edge)| edge.dist, |_| 0)
+			.map(|(cost, path)| (path, cost))
+	}
+	/// Simulate the greedy hops a Traverse packet to `dest` would take using known coordinates and the route_map, without sending anything
+	pub fn trace_route(&self, dest: RouteCoord, max_hops: usize) -> TracedRoute {
+		let coord_of = |node_id: NodeID| -> Option<RouteCoord> {
+			if node_id == self.node_id { self.route_coord }
+			else { self.remote(self.index_by_node_id(&node_id).ok()?).ok()?.route_coord }
+		};
+		let mut path = vec![self.node_id];
+		let mut current = self.node_id;
+		let mut current_coord = match self.route_coord { Some(coord) => coord, None => return TracedRoute { path, reached: false } };
+		for _ in 0..max_hops {
+			if current_coord == dest { return TracedRoute { path, reached: true } }
+			// This node routes using its peer list, other nodes are approximated by their known route_map neighbors
+			let candidates: Vec<NodeID> = if current == self.node_id {
+				self.peer_list.left_values().filter_map(|&node_idx| self.remote(node_idx).ok().map(|r| r.node_id)).collect()
+			} else {
+				self.route_map.neighbors_directed(current, petgraph::Direction::Outgoing)
+					.chain(self.route_map.neighbors_directed(current, petgraph::Direction::Incoming))
+					.collect()
+			};
+			let next = candidates.into_iter()
+				.filter(|node_id| !path.contains(node_id))
+				.filter_map(|node_id| Some((node_id, coord_of(node_id)?)))
+				.min_by_key(|(_, coord)| types::route_dist_sq(coord, &dest));
+			match next {
+				// Only move if it gets the packet closer, otherwise it is a dead end
+				Some((node_id, coord)) if types::route_dist_sq(&coord, &dest) < types::route_dist_sq(&current_coord, &dest) => {
+					path.push(node_id);
+					current = node_id;
+					current_coord = coord;
+				}
+				_ => return TracedRoute { path, reached: false },
+			}
+		}
+		let reached = current_coord == dest;
+		TracedRoute { path, reached }
+	}
+
 	// Returns true if action should be deleted and false if it should not be
 	pub fn parse_action(
 		&mut self,
@@ -348,6 +1091,64 @@ impl Node {
 					vec![NodePacket::ExchangeInfo(self.route_coord, 0, 0)],
 					outgoing,
 				)?;
+				// Once connected, check that the bootstrap node can reach us unsolicited
+				out_actions.push(NodeAction::TestReachability(remote_node_id)
+					.gen_condition(NodeActionCondition::Session(remote_node_id)));
+			}
+			NodeAction::BootstrapAny(seeds) => {
+				for &(seed_node_id, seed_net_addr) in &seeds {
+					self.connect(
+						seed_node_id,
+						SessionType::direct(seed_net_addr),
+						vec![NodePacket::ExchangeInfo(self.route_coord, 0, 0)],
+						outgoing,
+					)?;
+				}
+				let seed_ids = seeds.into_iter().map(|(node_id, _)| node_id).collect();
+				out_actions.push(NodeAction::ResolveBootstrap(seed_ids, self.ticks + self.config.bootstrap_timeout));
+			}
+			NodeAction::ResolveBootstrap(seed_ids, deadline) => {
+				let connected = seed_ids.iter().cloned().find(|seed_node_id| self.is_connected(seed_node_id));
+				if let Some(connected_node_id) = connected {
+					// Cancel handshakes to the other seeds
+					for seed_node_id in seed_ids.iter().filter(|&&id| id != connected_node_id) {
+						let seed_idx = self.index_by_node_id(seed_node_id)?;
+						self.remote_mut(seed_idx)?.pending_session = None;
+					}
+					out_actions.push(NodeAction::TestReachability(connected_node_id));
+				} else if self.ticks >= deadline {
+					log::error!("NodeID({}) failed to bootstrap off of any seed: {:?}", self.node_id, seed_ids);
+				} else {
+					return Ok(Some(NodeAction::ResolveBootstrap(seed_ids, deadline)));
+				}
+			}
+			NodeAction::Disconnect(remote_node_id) => {
+				let node_idx = self.index_by_node_id(&remote_node_id)?;
+				self.send_packet(node_idx, NodePacket::Disconnect, outgoing)?;
+				self.close_session(node_idx)?;
+			}
+			NodeAction::Bye(remote_node_id) => {
+				let node_idx = self.index_by_node_id(&remote_node_id)?;
+				self.release_peer(node_idx)?;
+				self.send_packet(node_idx, NodePacket::Bye, outgoing)?;
+			}
+			NodeAction::RequestIntroduction(intermediate_node_id, target_node_id) => {
+				self.send_packet(self.index_by_node_id(&intermediate_node_id)?, NodePacket::ConnectRequest(target_node_id), outgoing)?;
+			}
+			NodeAction::TestReachability(remote_node_id) => {
+				let nonce: u64 = self.gen_random();
+				self.reachability_probe = Some(nonce);
+				self.send_packet(self.index_by_node_id(&remote_node_id)?, NodePacket::ReachabilityProbe(nonce), outgoing)?;
+				out_actions.push(NodeAction::CheckReachability(nonce)
+					.gen_condition(NodeActionCondition::RunAt(self.ticks + self.config.reachability_timeout)));
+			}
+			NodeAction::CheckReachability(nonce) => {
+				// Probe is still pending after the timeout, nobody can connect to us unsolicited
+				if self.reachability_probe == Some(nonce) {
+					log::warn!("NodeID({}) did not receive reachability connect-back, acting as client only", self.node_id);
+					self.reachability_probe = None;
+					self.reachable = Some(false);
+				}
 			}
 			NodeAction::Connect(remote_node_id, session_type, ref packets) => {
 				self.connect(remote_node_id, session_type, packets.clone(), outgoing)?;
@@ -358,19 +1159,28 @@ impl Node {
 				remote_direct_count,
 				remote_ping,
 			) => {
-				self.route_map
-					.add_edge(remote_node_id, self.node_id, remote_ping);
+				self.record_edge(remote_node_id, self.node_id, remote_ping);
 
 				let self_route_coord = self.route_coord;
+				let self_ticks = self.ticks;
 
 				// Record Remote Coordinate
 				let node_idx = self.index_by_node_id(&remote_node_id)?;
 				let remote = self.remote_mut(node_idx)?;
 				let mut did_route_change = remote.route_coord != remote_route_coord;
-				remote.route_coord = remote_route_coord;
+				remote.set_route_coord(remote_route_coord, self_ticks);
 
 				// If this node has coord,
 				if let None = self.route_coord {
+					// Rough starting coordinate so this node can route while waiting for enough anchors, refined by CalcRouteCoord
+					if let Some(initial_route_coord) = self.initial_route_coord(remote_route_coord, remote_ping) {
+						self.set_self_route_coord(initial_route_coord);
+					}
+					out_actions.push(NodeAction::CalcRouteCoord);
+					did_route_change = false;
+				}
+				// Recalculate coordinate as more anchors become available
+				else if self.usable_anchor_count() > self.route_anchor_count && self.route_anchor_count < self.config.target_peer_count {
 					out_actions.push(NodeAction::CalcRouteCoord);
 					did_route_change = false;
 				}
@@ -378,16 +1188,41 @@ impl Node {
 					out_actions.push(NodeAction::CalculatePeers);
 				}
 				// If need more peers & remote has a peer, request pings
-				if self.direct_sorted.len() < TARGET_PEER_COUNT && remote_direct_count >= 2 {
+				if self.direct_sorted.len() < self.config.target_peer_count && remote_direct_count >= 2 {
 					self.send_packet(
 						node_idx,
-						NodePacket::RequestPings(TARGET_PEER_COUNT, self_route_coord),
+						NodePacket::RequestPings(self.config.target_peer_count, self_route_coord),
 						outgoing,
 					)?;
 				}
 			}
 			NodeAction::CalcRouteCoord => {
-				self.route_coord = Some(self.calculate_route_coord()?);
+				// Anchors keep their pinned coordinate
+				if self.anchor { return Ok(None) }
+				if self.coord_frozen_until.map_or(false, |until| self.ticks < until) { return Ok(None) }
+				self.coord_frozen_until = None;
+				let route_coord = match self.calculate_route_coord() {
+					Ok(route_coord) => route_coord,
+					// Keep the current coordinate until there are enough usable remotes
+					Err(NodeError::NoCalculatedRouteCoord) => return Ok(None),
+					Err(err) => Err(err)?,
+				};
+				self.recent_coords.push_back(route_coord);
+				while self.recent_coords.len() > self.config.oscillation_window { self.recent_coords.pop_front(); }
+				if self.is_oscillating() {
+					// Settle between the regions being flipped between and hold still so neighbors can converge
+					let count = self.recent_coords.len() as f64;
+					let centroid = self.recent_coords.iter().fold(Vector2::new(0.0, 0.0), |acc, c| acc + c.as_f64().coords) / count;
+					let frozen_coord = RouteCoord::from_f64(Point::from(centroid));
+					log::debug!("[{: >6}] NodeID({}) coordinate is oscillating, freezing at {}", self.ticks, self.node_id, frozen_coord);
+					self.set_self_route_coord(frozen_coord);
+					self.coord_frozen_until = Some(self.ticks + self.config.oscillation_freeze);
+					self.recent_coords.clear();
+				} else {
+					self.set_self_route_coord(route_coord);
+				}
+				self.route_anchor_count = self.usable_anchor_count();
+				self.normalize_coordinates();
 				out_actions.push(NodeAction::CalculatePeers);
 			}
 			NodeAction::ExchangeInformation(remote_node_id) => {
@@ -402,11 +1237,22 @@ impl Node {
 			NodeAction::CalculatePeers => {
 				// Collect the viable peers
 				let self_route_coord = self.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
-				let direct_nodes = self
+				let mut direct_nodes = self
 					.direct_sorted
 					.iter()
 					.map(|s| s.1.clone())
 					.collect::<Vec<NodeIdx>>();
+				// Pinned peers go first so they are never cut off
+				let is_pinned = |node_idx: &NodeIdx| self.remote(*node_idx).map_or(false, |r| self.pinned_peers.contains(&r.node_id));
+				direct_nodes.sort_by_key(|node_idx| !is_pinned(node_idx));
+				let max_loss_rate = self.config.max_peer_loss_rate;
+				let min_samples = self.config.min_peer_samples;
+				let previous_peers = self.peer_list.left_values().cloned().collect::<HashSet<NodeIdx>>();
+				// Refusals expire so remotes whose incoming budget has freed up are tried again
+				let ticks = self.ticks;
+				for &node_idx in &direct_nodes {
+					let _: Result<(), NodeError> = try { self.remote_mut(node_idx)?.session_mut()?.direct_mut()?.expire_peer_refusal(ticks) };
+				}
 				self.peer_list = direct_nodes
 					.iter()
 					.filter_map(|&node_idx| {
@@ -414,7 +1260,25 @@ impl Node {
 						self.remote(node_idx)
 							.ok()
 							.map(|remote| {
-								if let Some(route_coord) = remote.is_viable_peer(self_route_coord) {
+								if self.released_peers.contains(&remote.node_id) {
+									return None;
+								}
+								if self.pinned_peers.contains(&remote.node_id) {
+									return remote.route_coord.map(|route_coord| (node_idx, route_coord));
+								}
+								// Remotes that refused to peer with this node can't be routed through
+								if remote.session().ok().and_then(|s| s.direct().ok()).map_or(false, |d| d.peer_status.contains(PeerStatus::Refused)) {
+									return None;
+								}
+								// Flaky peers keep their session but don't carry routed traffic
+								if remote.session().map_or(false, |s| s.tracker.loss_rate() > max_loss_rate) {
+									return None;
+								}
+								// Links without enough samples stay connected but don't influence routing yet
+								if remote.session().map_or(true, |s| s.tracker.ping_count < min_samples) {
+									return None;
+								}
+								if let Some(route_coord) = self.peer_policy.viable_peer(remote, self_route_coord) {
 									Some((node_idx, route_coord))
 								} else {
 									None
@@ -422,16 +1286,35 @@ impl Node {
 							})
 							.flatten()
 					})
-					.take(TARGET_PEER_COUNT)
+					.take(self.config.target_peer_count.max(self.pinned_peers.len()))
 					.collect();
 
+				let changes = self.peer_list.left_values().filter(|node_idx| !previous_peers.contains(node_idx)).count()
+					+ previous_peers.iter().filter(|node_idx| !self.peer_list.contains_left(node_idx)).count();
+				self.record_peer_churn(changes);
+
 				// Notify Peers if just became peer
 				let num_peers = self.peer_list.len();
+				let debounce = self.config.peer_notify_debounce;
+				let mut recheck_at = None;
 				for node_idx in direct_nodes {
 					let toggle = self.peer_list.contains_left(&node_idx);
 					let remote = self.remote(node_idx)?;
 					let dist = remote.session()?.tracker.dist_avg;
-					match (!remote.session()?.is_peer(), toggle) {
+					let notified = remote.session()?.is_peer();
+					// Membership changes are only announced once they have held for `peer_notify_debounce` ticks
+					if notified == toggle {
+						self.peer_changes.remove(&node_idx);
+					} else if debounce != 0 {
+						let changed_at = *self.peer_changes.entry(node_idx).or_insert(self.ticks);
+						if self.ticks < changed_at + debounce {
+							let due = changed_at + debounce;
+							recheck_at = Some(recheck_at.map_or(due, |at: usize| at.min(due)));
+							continue;
+						}
+						self.peer_changes.remove(&node_idx);
+					}
+					match (notified, toggle) {
 						(false, true) => {
 							// Notify that this node thinks of other node as a direct peer
 							self.send_packet(
@@ -441,17 +1324,8 @@ impl Node {
 							)?;
 						}
 						(true, false) => {
-							// Notify that this node no longer things of other node as a direct peer, so perhaps other node should drop connection
-							self.send_packet(
-								node_idx,
-								NodePacket::PeerNotify(
-									usize::MAX,
-									self_route_coord,
-									num_peers,
-									dist,
-								),
-								outgoing,
-							)?;
+							// Notify that this node no longer thinks of other node as a direct peer, so perhaps other node should drop connection
+							self.send_packet(node_idx, NodePacket::PeerDrop, outgoing)?;
 						}
 						_ => {}
 					}
@@ -460,12 +1334,16 @@ impl Node {
 						.direct_mut()?
 						.set_peer(toggle);
 				}
+				if let Some(recheck_at) = recheck_at {
+					out_actions.push(NodeAction::CalculatePeers.gen_condition(NodeActionCondition::RunAt(recheck_at)));
+				}
 
 				// If have enough peers & want to host node as public, write RouteCoord to DHT
-				if self.peer_list.len() >= TARGET_PEER_COUNT
-					&& self.is_public && self.public_route != self.route_coord
+				if self.peer_list.len() >= self.config.target_peer_count
+					&& self.is_public && self.is_reachable() && self.should_publish(self_route_coord)
 				{
 					self.public_route = self.route_coord;
+					self.last_published = Some(self.ticks);
 					outgoing.push(InternetPacket::gen_request(
 						self.net_addr,
 						InternetRequest::RouteCoordDHTWrite(self.node_id, self_route_coord),
@@ -489,10 +1367,53 @@ impl Node {
 					);
 				}
 			}
+			NodeAction::ChallengeCoord(remote_node_id) => {
+				self.send_packet(self.index_by_node_id(&remote_node_id)?, NodePacket::CoordChallenge, outgoing)?;
+			}
+			NodeAction::PruneRoutes => {
+				self.prune_requested = true;
+			}
+			NodeAction::GossipCoords => {
+				// Share freshest known coordinates (including our own) with all peers
+				let self_ticks = self.ticks;
+				let mut entries = self.remotes.values()
+					.filter_map(|remote| Some((remote.node_id, remote.route_coord?, self_ticks.saturating_sub(remote.coord_updated?))))
+					.collect::<Vec<(NodeID, RouteCoord, usize)>>();
+				entries.sort_unstable_by_key(|&(_, _, age)| age);
+				if let Some(route_coord) = self.route_coord {
+					entries.insert(0, (self.node_id, route_coord, 0));
+				}
+				entries.truncate(self.config.gossip_sample_size);
+				let peers = self.peer_list.left_values().cloned().collect::<Vec<NodeIdx>>();
+				for node_idx in peers {
+					let remote_node_id = self.remote(node_idx)?.node_id;
+					let sample = entries.iter().filter(|(node_id, ..)| *node_id != remote_node_id).cloned().collect();
+					self.send_packet(node_idx, NodePacket::RouteCoordGossip(sample), outgoing)?;
+				}
+			}
 			NodeAction::RequestRouteCoord(remote_node_id) => {
+				// Ask the remote directly if there is already a session with it
+				let session_idx = self.index_by_node_id(&remote_node_id).ok()
+					.filter(|&node_idx| self.remote(node_idx).map_or(false, |r| r.session_active()));
+				if let Some(node_idx) = session_idx {
+					self.send_packet(node_idx, NodePacket::RequestCoord, outgoing)?;
+				} else {
+					outgoing.push(InternetPacket::gen_request(
+						self.net_addr,
+						InternetRequest::RouteCoordDHTRead(remote_node_id),
+					));
+					let deadline = self.ticks + self.config.dht_timeout;
+					self.pending_dht.entry(remote_node_id).or_insert((deadline, 1)).0 = deadline;
+				}
+			}
+			NodeAction::QueryCoords(remote_node_id, mut node_ids) => {
+				node_ids.truncate(MAX_COORD_QUERY);
+				self.send_packet(self.index_by_node_id(&remote_node_id)?, NodePacket::CoordQuery(node_ids), outgoing)?;
+			}
+			NodeAction::RequestRegion(route_coord, count) => {
 				outgoing.push(InternetPacket::gen_request(
 					self.net_addr,
-					InternetRequest::RouteCoordDHTRead(remote_node_id),
+					InternetRequest::RouteCoordDHTRegionRead(Node::coordinate_to_dht_key(route_coord), count),
 				));
 			}
 			NodeAction::ConnectTraversed(remote_node_id, packets) => {
@@ -515,39 +1436,137 @@ impl Node {
 			}
 			NodeAction::ConnectRouted(remote_node_id, hops) => {
 				let self_route_coord = self.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
-				// Check if Remote Route Coord was allready requested
+				let deadline = self.ticks + self.config.routed_connect_timeout;
 				let (_, remote) = self.add_remote(remote_node_id.clone())?;
 				if let Some(remote_route_coord) = remote.route_coord {
-					let self_route_coord = self_route_coord.map(|s| s as f64);
-					let remote_route_coord = remote_route_coord.map(|s| s as f64);
-					let diff = (remote_route_coord - self_route_coord) / hops as f64;
-					let mut routes = Vec::with_capacity(hops);
+					// Route through the peers closest to evenly spaced points between this node and the target
+					let self_route_coord = self_route_coord.as_f64();
+					let diff = (remote_route_coord.as_f64() - self_route_coord) / hops.max(1) as f64;
+					let mut proxy_nodes = Vec::with_capacity(hops);
 					for i in 1..hops {
-						routes.push(self_route_coord + diff * i as f64);
+						let waypoint = RouteCoord::from_f64(self_route_coord + diff * i as f64);
+						let proxy = self.find_closest_peer(&waypoint).ok()
+							.and_then(|node_idx| self.remote(node_idx).ok())
+							.filter(|proxy| proxy.node_id != remote_node_id && proxy.route_coord.is_some())
+							.and_then(|proxy| Some(proxy.session().ok()?.session_id));
+						match proxy {
+							Some(session_id) => if !proxy_nodes.contains(&session_id) { proxy_nodes.push(session_id) },
+							None => {
+								self.events.push(NodeEvent::RoutedConnect { target: remote_node_id, result: Err(RoutedConnectFailure::NoPath) });
+								return Ok(None);
+							}
+						}
 					}
-					println!("Routes: {:?}", routes);
-				//use nalgebra::distance_squared;
-				// Find nearest node
-				//let nearest_peer = self.peer_list.iter().min_by_key(|(id,&r)|distance_squared(&routes[0], &r.map(|s|s as f64)) as i64);
-
-				//self.routed_connect(remote_node_id, outgoing);
-				//self.remote_mut(self.index_by_node_id(&remote_node_id)?)?.connect_routed(routes);
+					let route_hops = proxy_nodes.len() + 1;
+					if let Err(err) = self.connect(remote_node_id, SessionType::routed(remote_route_coord, proxy_nodes), vec![], outgoing) {
+						log::debug!("NodeID({}) failed to send routed handshake to NodeID({}): {:?}", self.node_id, remote_node_id, err);
+						self.events.push(NodeEvent::RoutedConnect { target: remote_node_id, result: Err(RoutedConnectFailure::NoPath) });
+						return Ok(None);
+					}
+					out_actions.push(NodeAction::ResolveRoutedConnect(remote_node_id, route_hops, deadline));
 				} else {
-					// Otherwise, Request it and await Condition for next ConnectRouted
+					// Otherwise, Request it and wait for it to arrive before the next ConnectRouted
 					out_actions.push(NodeAction::RequestRouteCoord(remote_node_id));
-					out_actions.push(
-						NodeAction::ConnectRouted(remote_node_id, hops)
-							.gen_condition(NodeActionCondition::RemoteRouteCoord(remote_node_id)),
-					);
+					out_actions.push(NodeAction::ResolveRoutedConnect(remote_node_id, hops, deadline));
+				}
+			}
+			NodeAction::RetransmitHandshake(remote_node_id, session_id, retransmits) => {
+				let remote = self.remote(self.index_by_node_id(&remote_node_id)?)?;
+				// Stop once the handshake was acknowledged, cancelled or replaced by a newer one
+				let (started, net_addr) = match remote.pending_session.as_deref() {
+					Some((pending_id, started, _, SessionType::Direct(direct))) if *pending_id == session_id => (*started, direct.net_addr),
+					_ => return Ok(None),
+				};
+				if self.ticks >= started + self.config.handshake_timeout {
+					log::debug!("[{: >6}] NodeID({}) gave up retransmitting Handshake to NodeID({})", self.ticks, self.node_id, remote_node_id);
+					return Ok(None);
 				}
+				let encryption = NodeEncryption::Handshake { recipient: remote_node_id, session_id, signer: self.node_id };
+				outgoing.push(encryption.package(net_addr, self.config.encryption));
+				out_actions.push(self.schedule_handshake_retransmit(remote_node_id, session_id, retransmits + 1));
+			}
+			NodeAction::ResolveRoutedConnect(remote_node_id, hops, deadline) => {
+				let remote = self.index_by_node_id(&remote_node_id).ok().and_then(|node_idx| self.remote(node_idx).ok());
+				let (connected, pending, has_coord) = remote.map_or((false, false, false), |r| (r.session_active(), r.pending_session.is_some(), r.route_coord.is_some()));
+				let result = if connected {
+					Ok(hops)
+				} else if !pending && has_coord {
+					out_actions.push(NodeAction::ConnectRouted(remote_node_id, hops));
+					return Ok(None);
+				} else if self.ticks >= deadline {
+					Err(if pending { RoutedConnectFailure::Timeout } else { RoutedConnectFailure::NoRouteCoord })
+				} else {
+					return Ok(Some(NodeAction::ResolveRoutedConnect(remote_node_id, hops, deadline)));
+				};
+				self.events.push(NodeEvent::RoutedConnect { target: remote_node_id, result });
+			}
+			NodeAction::SendData(remote_node_id, data) => {
+				self.send_packet(
+					self.index_by_node_id(&remote_node_id)?,
+					NodePacket::Data(data),
+					outgoing,
+				)?;
+			}
+			NodeAction::ProbePath(destination) => {
+				let origin = self.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
+				let probe_id = self.gen_random();
+				let next_idx = self.find_closest_peer(&destination)?;
+				let probe = PathProbe { probe_id, destination, origin, sent_at: self.ticks, hops: 1, returning: false };
+				self.send_packet(next_idx, NodePacket::PathProbe(Box::new(probe)), outgoing)?;
+				self.pending_probes.insert(probe_id, destination);
 			}
-			NodeAction::SendData(remote_node_id, data) => {
+			NodeAction::SendApplication(remote_node_id, category, data) => {
 				self.send_packet(
 					self.index_by_node_id(&remote_node_id)?,
-					NodePacket::Data(data),
+					NodePacket::Application(category, data),
 					outgoing,
 				)?;
 			}
+			NodeAction::Retry { remaining, interval, action } => {
+				let success_condition = action.success_condition();
+				if let Some(condition) = &success_condition {
+					if condition.check(self).unwrap_or(false) { return Ok(None) }
+				}
+				if remaining == 0 {
+					log::warn!("NodeID({}) gave up retrying action: {:?}", self.node_id, action);
+					return Ok(None);
+				}
+				let result = self.parse_action((*action).clone(), outgoing, out_actions);
+				if let Err(err) = &result {
+					log::debug!("NodeID({}) retried action {:?} errored: {:?}", self.node_id, action, err);
+				}
+				// Actions without a success condition are done once they complete without error
+				let completed = matches!(result, Ok(None)) && success_condition.is_none();
+				if !completed {
+					out_actions.push(NodeAction::Retry { remaining: remaining - 1, interval, action }
+						.gen_condition(NodeActionCondition::RunAt(self.ticks + interval)));
+				}
+			}
+			NodeAction::Sequence(mut steps) => {
+				while !steps.is_empty() {
+					let step = steps.remove(0);
+					let success_condition = step.success_condition();
+					match self.parse_action(step, outgoing, out_actions) {
+						// Steps like Connect return as soon as they send their first packet, wait until they have had their effect (like Retry does)
+						Ok(None) => if let Some(condition) = success_condition {
+							if !condition.check(self).unwrap_or(false) {
+								// An empty Sequence is a no-op step that completes once the condition wrapping it is satisfied
+								steps.insert(0, NodeAction::Sequence(vec![]).gen_condition(condition));
+								return Ok(Some(NodeAction::Sequence(steps)));
+							}
+						}
+						// Step isn't done yet, wait for it before moving on
+						Ok(Some(pending)) => {
+							steps.insert(0, pending);
+							return Ok(Some(NodeAction::Sequence(steps)));
+						}
+						Err(err) => {
+							log::debug!("NodeID({}) sequence step failed, aborting {} remaining steps", self.node_id, steps.len());
+							return Err(err);
+						}
+					}
+				}
+			}
 			NodeAction::Condition(condition, embedded_action) => {
 				// Returns embedded action if condition is satisfied (e.g. check() returns true), else returns false to prevent action from being deleted
 				if condition.check(self)? {
@@ -563,16 +1582,30 @@ impl Node {
 		//log::trace!("[{: >6}] NodeID({}) Completed Action: {:?}", self.ticks, self.node_id, action);
 		Ok(None) // By default don't return action
 	}
+	/// Handle a packet received over a session
+	/// `depth` is how many packets this one is nested within (e.g. through ConnectionInit or Traverse), top-level packets have depth 0
 	pub fn parse_node_packet(
 		&mut self,
 		return_node_idx: NodeIdx,
 		received_packet: NodePacket,
 		outgoing: &mut PacketVec,
+		depth: usize,
 	) -> Result<(), NodeError> {
-		let self_ticks = self.ticks;
+		if depth > self.config.max_packet_depth {
+			Err(NodeError::PacketNestingTooDeep { depth, max: self.config.max_packet_depth })?;
+		}
+		let (self_ticks, self_node_id) = (self.ticks, self.node_id);
 		let return_remote = self.remote_mut(return_node_idx)?;
 		let return_node_id = return_remote.node_id;
-		let packet_last_received = return_remote.session_mut()?.check_packet_time(
+		// Session may have been closed earlier in this tick (e.g. by a Disconnect nested in the same ConnectionInit)
+		let return_session = match return_remote.session.as_mut() {
+			Some(session) => session,
+			None => {
+				log::trace!("[{: >6}] NodeID({}) dropping {:?} from NodeID({}) with no session", self_ticks, self_node_id, received_packet, return_node_id);
+				return Ok(());
+			}
+		};
+		let packet_last_received = return_session.check_packet_time(
 			&received_packet,
 			return_node_id,
 			self_ticks,
@@ -589,23 +1622,22 @@ impl Node {
 		match received_packet {
 			NodePacket::ConnectionInit(ping_id, packets) => {
 				// Acknowledge ping
-				let distance = self
-					.remote_mut(return_node_idx)?
-					.session_mut()?
-					.tracker
-					.acknowledge_ping(ping_id, self_ticks)?;
-				self.route_map
-					.add_edge(self.node_id, return_node_id, distance);
-				self.direct_sorted.insert(distance, return_node_idx);
+				let session = self.remote_mut(return_node_idx)?.session_mut()?;
+				session.finish_setup(self_ticks);
+				let distance = session.tracker.acknowledge_ping(ping_id, self_ticks)?;
+				self.record_edge(self.node_id, return_node_id, distance);
+				self.set_direct_dist(return_node_idx, return_node_id, distance);
 				// Recursively parse packets
 				for packet in packets {
-					self.parse_node_packet(return_node_idx, packet, outgoing)?;
+					self.parse_node_packet(return_node_idx, packet, outgoing, depth + 1)?;
 				}
+				self.announce_anchor(return_node_idx, outgoing)?;
 			}
 			NodePacket::ExchangeInfo(remote_route_coord, _remote_direct_count, remote_ping) => {
+				// A single remote is too few to calculate from, NodeID 0 starts the coordinate frame at the origin
 				if self.node_id == 0 && self.direct_sorted.len() == 1 && self.route_coord.is_none()
 				{
-					self.route_coord = Some(self.calculate_route_coord()?);
+					self.set_self_route_coord(RouteCoord::origin());
 				}
 
 				// Note Data, Update Remote
@@ -640,14 +1672,8 @@ impl Node {
 				));
 			}
 			NodePacket::ProposeRouteCoords(route_coord_proposal, remote_route_coord_proposal) => {
-				let acceptable = if self.route_coord.is_none() {
-					self.route_coord = Some(route_coord_proposal);
-					self.remote_mut(return_node_idx)?.route_coord =
-						Some(remote_route_coord_proposal);
-					true
-				} else {
-					false
-				};
+				let acceptable = self.route_coord.is_none()
+					&& self.validate_and_adopt_coordinate(return_node_idx, route_coord_proposal, remote_route_coord_proposal)?;
 				self.send_packet(
 					return_node_idx,
 					NodePacket::ProposeRouteCoordsResponse(
@@ -664,8 +1690,21 @@ impl Node {
 				accepted,
 			) => {
 				if accepted {
-					self.route_coord = Some(initial_self_proposal);
-					self.remote_mut(return_node_idx)?.route_coord = Some(initial_remote_proposal);
+					// This node may have gotten a coordinate some other way while the proposal was in flight
+					let adopted = self.route_coord.is_none()
+						&& self.validate_and_adopt_coordinate(return_node_idx, initial_self_proposal, initial_remote_proposal)?;
+					if !adopted {
+						self.send_packet(return_node_idx, NodePacket::RetractRouteCoords(initial_remote_proposal, initial_self_proposal), outgoing)?;
+					}
+				}
+			}
+			NodePacket::RetractRouteCoords(route_coord_proposal, remote_route_coord_proposal) => {
+				// Only undo the proposal if neither coordinate has changed since it was adopted
+				let self_route_coord = self.route_coord;
+				let remote = self.remote_mut(return_node_idx)?;
+				if self_route_coord == Some(route_coord_proposal) && remote.route_coord == Some(remote_route_coord_proposal) {
+					remote.set_route_coord(None, self_ticks);
+					self.reset_coordinate();
 				}
 			}
 			NodePacket::RequestPings(requests, requester_route_coord) => {
@@ -679,16 +1718,15 @@ impl Node {
 
 				// TODO: Use vpsearch Tree datastructure for optimal efficiency
 				// Locate closest nodes (TODO: Locate nodes that have a wide diversity of angles for optimum efficiency)
-				self.remote_mut(return_node_idx)?.route_coord = requester_route_coord;
+				self.remote_mut(return_node_idx)?.set_route_coord(requester_route_coord, self_ticks);
 				let closest_nodes = if let Some(route_coord) = requester_route_coord {
-					let point_target = route_coord.map(|s| s as f64);
 					let mut sorted = self
 						.direct_sorted
 						.iter()
 						.filter_map(|(&_, &node_idx)| {
 							self.remote(node_idx).ok().map(|remote| {
 								if let Some(p) = remote.route_coord {
-									Some((node_idx, nalgebra::distance_squared(&p.map(|s| s as f64), &point_target) as u64))
+									Some((node_idx, types::route_dist_sq(&p, &route_coord)))
 								} else { None }
 							}).flatten()
 						})
@@ -724,6 +1762,10 @@ impl Node {
 				if self.node_id == requesting_node_id || self.route_coord.is_none() {
 					return Ok(());
 				}
+				// Don't automatically reconnect to recently disconnected nodes
+				if self.is_tombstoned(&requesting_node_id) {
+					return Ok(());
+				}
 				let distance_self_to_return =
 					self.remote(return_node_idx)?.session()?.tracker.dist_avg;
 
@@ -747,7 +1789,7 @@ impl Node {
 			}
 			NodePacket::AcceptWantPing(intermediate_node_id, return_to_intermediate_distance) => {
 				let avg_dist = self.remote(return_node_idx)?.session()?.dist();
-				self.route_map.add_edge(
+				self.record_edge(
 					return_node_id,
 					intermediate_node_id,
 					return_to_intermediate_distance,
@@ -770,10 +1812,18 @@ impl Node {
 				// Record peer rank
 				//let node_idx = self.index_by_session_id(session_id: &SessionID)
 				//let session = self.remote_mut(return_node_idx)?.session_mut()?;
-				self.remote_mut(return_node_idx)?
-					.session_mut()?
-					.direct_mut()?
-					.record_peer_notify(rank);
+				// Incoming-only peers have their own budget, refuse new ones once it is used up
+				let peer_status = self.remote(return_node_idx)?.session()?.direct()?.peer_status;
+				let new_incoming_only = rank != usize::MAX && !peer_status.intersects(PeerStatus::Mutual);
+				if new_incoming_only && self.incoming_peers().len() >= self.config.max_incoming_peers {
+					log::debug!("[{: >6}] NodeID({}) has no room for incoming peer NodeID({}), refusing", self_ticks, self.node_id, return_node_id);
+					self.send_packet(return_node_idx, NodePacket::PeerRefuse, outgoing)?;
+				} else {
+					self.remote_mut(return_node_idx)?
+						.session_mut()?
+						.direct_mut()?
+						.record_peer_notify(rank);
+				}
 				// Update remote
 				self.action(NodeAction::UpdateRemote(
 					return_node_id,
@@ -782,6 +1832,131 @@ impl Node {
 					peer_distance,
 				));
 			}
+			NodePacket::ConnectRequest(target_node_id) => {
+				// Forward requester's address to target so that target initiates the connection
+				let requester_net_addr = self.remote(return_node_idx)?.session()?.direct()?.net_addr;
+				let target_idx = self.index_by_node_id(&target_node_id)?;
+				self.send_packet(target_idx, NodePacket::Introduction(return_node_id, requester_net_addr), outgoing)?;
+			}
+			NodePacket::Introduction(requester_node_id, requester_net_addr) => {
+				if requester_node_id == self.node_id || self.is_tombstoned(&requester_node_id) { return Ok(()) }
+				let (_, requester) = self.add_remote(requester_node_id)?;
+				if requester.session.is_none() && requester.pending_session.is_none() {
+					self.action(NodeAction::Connect(
+						requester_node_id,
+						SessionType::direct(requester_net_addr),
+						vec![NodePacket::ExchangeInfo(self.route_coord, 0, 0)],
+					));
+				}
+			}
+			NodePacket::Disconnect => {
+				self.close_session(return_node_idx)?;
+			}
+			NodePacket::Bye => {
+				self.release_peer(return_node_idx)?;
+			}
+			NodePacket::Anchor(route_coord) => {
+				// Only weight the remote as an anchor once its pinned coordinate is consistent with what this node and mutual anchors measured
+				let anchors = self.coord_anchors(return_node_idx)?;
+				let remote = self.remote_mut(return_node_idx)?;
+				let is_anchor = match route_coord {
+					Some(claimed_route_coord) => remote.verify_route_coord(claimed_route_coord, &anchors),
+					None => false,
+				};
+				if route_coord.is_some() && !is_anchor {
+					log::debug!("NodeID({}) claims an anchor RouteCoord inconsistent with measured distances, ignoring", return_node_id);
+				}
+				let changed = remote.is_anchor != is_anchor || (is_anchor && remote.route_coord != route_coord);
+				remote.is_anchor = is_anchor;
+				if is_anchor { remote.set_route_coord(route_coord, self_ticks) }
+				// Recalculate now that this remote carries a different weight
+				if changed { self.action(NodeAction::CalcRouteCoord) }
+			}
+			NodePacket::PeerRefuse => {
+				let refused_until = self_ticks + self.config.peer_refuse_cooldown;
+				self.remote_mut(return_node_idx)?
+					.session_mut()?
+					.direct_mut()?
+					.record_peer_refused(refused_until);
+				if self.peer_list.remove_by_left(&return_node_idx).is_some() {
+					self.action(NodeAction::CalculatePeers);
+				}
+				self.action(NodeAction::CalculatePeers.gen_condition(NodeActionCondition::RunAt(refused_until)));
+			}
+			NodePacket::PeerDrop => {
+				self.remote_mut(return_node_idx)?
+					.session_mut()?
+					.direct_mut()?
+					.record_peer_drop();
+			}
+			NodePacket::AddressUpdate(new_net_addr) => {
+				self.remote_mut(return_node_idx)?
+					.session_mut()?
+					.direct_mut()?
+					.net_addr = new_net_addr;
+			}
+			NodePacket::ReachabilityProbe(nonce) => {
+				// Send unsolicited notify outside of the session to the address the remote connected from
+				let remote_net_addr = self.remote(return_node_idx)?.session()?.direct()?.net_addr;
+				let encryption = NodeEncryption::Notify { recipient: return_node_id, data: nonce, sender: self.node_id };
+				outgoing.push(encryption.package(remote_net_addr, self.config.encryption));
+			}
+			NodePacket::CoordChallenge => {
+				self.send_packet(return_node_idx, NodePacket::CoordResponse(self.route_coord), outgoing)?;
+			}
+			NodePacket::CoordResponse(claimed_route_coord) => {
+				let claimed_route_coord = match claimed_route_coord { Some(route_coord) => route_coord, None => return Ok(()) };
+				// Only adopt the claimed coordinate once it is consistent with what this node and mutual anchors measured
+				let anchors = self.coord_anchors(return_node_idx)?;
+				let remote = self.remote_mut(return_node_idx)?;
+				if remote.verify_route_coord(claimed_route_coord, &anchors) {
+					remote.set_route_coord(Some(claimed_route_coord), self_ticks);
+				} else if !remote.coord_trusted {
+					log::warn!("NodeID({}) claims a RouteCoord inconsistent with measured distances, marking untrusted", return_node_id);
+				} else {
+					log::debug!("NodeID({}) claimed RouteCoord {} is inconsistent with measured distances, ignoring", return_node_id, claimed_route_coord);
+				}
+			}
+			NodePacket::RequestCoord => {
+				self.send_packet(return_node_idx, NodePacket::RequestCoordResponse(self.route_coord), outgoing)?;
+			}
+			NodePacket::RequestCoordResponse(route_coord) => {
+				if route_coord.is_some() {
+					self.remote_mut(return_node_idx)?.set_route_coord(route_coord, self_ticks);
+				}
+			}
+			NodePacket::CoordQuery(node_ids) => {
+				let response = node_ids.into_iter().take(MAX_COORD_QUERY).map(|node_id| {
+					let route_coord = if node_id == self.node_id { self.route_coord } else {
+						self.index_by_node_id(&node_id).ok().and_then(|node_idx| self.remote(node_idx).ok()?.route_coord)
+					};
+					(node_id, route_coord)
+				}).collect();
+				self.send_packet(return_node_idx, NodePacket::CoordQueryResponse(response), outgoing)?;
+			}
+			NodePacket::CoordQueryResponse(entries) => {
+				for (node_id, route_coord) in entries.into_iter().take(MAX_COORD_QUERY) {
+					let route_coord = if let Some(route_coord) = route_coord { route_coord } else { continue };
+					if node_id == self.node_id { continue }
+					let (_, remote) = self.add_remote(node_id)?;
+					// Secondhand coordinates only fill gaps, except for the responder's own which is authoritative
+					if node_id == return_node_id || remote.route_coord.is_none() {
+						remote.set_route_coord(Some(route_coord), self_ticks);
+					}
+				}
+			}
+			NodePacket::RouteCoordGossip(entries) => {
+				for (node_id, route_coord, age) in entries.into_iter().take(self.config.gossip_sample_size) {
+					if node_id == self.node_id { continue }
+					let learned_at = self_ticks.saturating_sub(age);
+					let (_, remote) = self.add_remote(node_id)?;
+					// Newer coordinates win
+					if remote.coord_updated.map_or(true, |updated| updated < learned_at) {
+						remote.route_coord = Some(route_coord);
+						remote.coord_updated = Some(learned_at);
+					}
+				}
+			}
 			NodePacket::Traverse(ref traversal_packet) => {
 				let closest_peer_idx = self.find_closest_peer(&traversal_packet.destination)?;
 				let closest_peer = self.remote(closest_peer_idx)?;
@@ -798,7 +1973,18 @@ impl Node {
 							SessionType::traversed(return_route_coord),
 							outgoing,
 						)? {
-							self.parse_node_packet(node_idx, packet, outgoing)?;
+							if let Some(sent_at) = traversal_packet.ack {
+								let ack = NodeEncryption::TraversalAck {
+									recipient: self.remote(node_idx)?.node_id,
+									acknowledger: self.node_id,
+									sent_at,
+								};
+								// The ack needs an origin too, Traverse packets without one are dropped by their destination
+								let self_route_coord = self.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
+								let return_peer_idx = self.find_closest_peer(&return_route_coord)?;
+								self.send_packet(return_peer_idx, TraversedPacket::new(return_route_coord, ack, Some(self_route_coord)), outgoing)?;
+							}
+							self.parse_node_packet(node_idx, packet, outgoing, depth + 1)?;
 						}
 					} else {
 						log::info!(
@@ -808,9 +1994,18 @@ impl Node {
 						);
 					}
 				} else {
+					// Only relay for destinations within this node's service region
+					if let (Some(radius), Some(self_route_coord)) = (self.config.relay_radius, self.route_coord) {
+						if types::route_dist(&self_route_coord, &traversal_packet.destination) > radius {
+							Err(NodeError::OutsideRelayRadius { destination: traversal_packet.destination, radius })?;
+						}
+					}
 					// Check if next node is not node that I received the packet from
-					if return_node_id != closest_peer.node_id {
+					if self.is_loopback(closest_peer_idx) {
+						log::error!("NodeID({}) next hop for Traverse packet resolves to itself, dropping", self.node_id);
+					} else if return_node_id != closest_peer.node_id {
 						self.send_packet(closest_peer_idx, received_packet, outgoing)?;
+						self.record_relay_client(return_node_id);
 					} else if let Some(_origin) = traversal_packet.origin {
 						// Else, try to traverse packet back to origin
 						log::error!("Packet Was Returned back, there seems to be a packet loop");
@@ -819,6 +2014,49 @@ impl Node {
 					}
 				}
 			}
+			NodePacket::PathProbe(mut probe) => {
+				if probe.returning {
+					if let Some(destination) = self.pending_probes.remove(&probe.probe_id) {
+						self.events.push(NodeEvent::PathProbe { destination, rtt: self_ticks.saturating_sub(probe.sent_at), hops: probe.hops });
+						return Ok(());
+					}
+				}
+				let self_route_coord = self.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
+				let target = if probe.returning { probe.origin } else { probe.destination };
+				let mut next_idx = self.find_closest_peer(&target)?;
+				let makes_progress = |node: &Node, node_idx: NodeIdx, target: &RouteCoord| node.remote(node_idx).ok().and_then(|r| r.route_coord)
+					.map_or(false, |c| types::route_dist_sq(&c, target) < types::route_dist_sq(&self_route_coord, target));
+				if !makes_progress(self, next_idx, &target) {
+					if probe.returning {
+						log::warn!("NodeID({}) has no route back to origin {} of a path probe, dropping", self.node_id, probe.origin);
+						return Ok(());
+					}
+					// No peer is closer to the destination than this node, so the probe has arrived
+					probe.returning = true;
+					next_idx = self.find_closest_peer(&probe.origin)?;
+				}
+				probe.hops += 1;
+				self.send_packet(next_idx, NodePacket::PathProbe(probe), outgoing)?;
+			}
+			NodePacket::Reliable(request_id, packet) => {
+				self.send_packet(return_node_idx, NodePacket::Ack(request_id), outgoing)?;
+				if self.remote_mut(return_node_idx)?.session_mut()?.record_request(request_id) {
+					self.parse_node_packet(return_node_idx, *packet, outgoing, depth + 1)?;
+				}
+			}
+			NodePacket::Ack(request_id) => {
+				self.remote_mut(return_node_idx)?.session_mut()?.pending_acks.remove(&request_id);
+			}
+			NodePacket::Application(category, data) => {
+				if let Some(handler) = self.packet_handlers.get_mut(&category) {
+					// Handler may reply with data of the same category
+					if let Some(response) = handler(return_node_id, data) {
+						self.send_packet(return_node_idx, NodePacket::Application(category, response), outgoing)?;
+					}
+				} else {
+					log::warn!("NodeID({}) received Application packet with unregistered category {} from NodeID({})", self.node_id, category, return_node_id);
+				}
+			}
 			NodePacket::Data(data) => {
 				println!(
 					"{} -> {}, Data: {}",
@@ -831,6 +2069,257 @@ impl Node {
 		Ok(())
 	}
 
+	/// Import remote coordinates from an external source (e.g. a snapshot).
+	/// Imported coordinates have unknown age, so they never replace a coordinate that was learned live
+	pub fn merge_known_coords(&mut self, coords: &[(NodeID, RouteCoord)]) -> Result<(), NodeError> {
+		for &(node_id, route_coord) in coords {
+			if node_id == self.node_id { continue }
+			let (_, remote) = self.add_remote(node_id)?;
+			if remote.coord_updated.is_none() {
+				remote.route_coord = Some(route_coord);
+			}
+		}
+		if self.route_coord.is_some() {
+			self.action(NodeAction::CalculatePeers);
+		}
+		Ok(())
+	}
+	/// Import distances observed by another node into route_map.
+	/// Imported distances are secondhand, so they are blended in with `config.imported_edge_weight` times the usual weight, and never touch this node's own links
+	pub fn merge_route_map(&mut self, edges: &[(NodeID, NodeID, RouteScalar)]) {
+		let decay = self.config.edge_decay * self.config.imported_edge_weight;
+		for &(from, to, dist) in edges {
+			if from == to || from == self.node_id || to == self.node_id { continue }
+			self.blend_edge(from, to, dist, decay);
+		}
+	}
+	/// Adopt a proposed coordinate frame for this node and a remote, only if the distance between the coordinates agrees with the measured distance to the remote.
+	/// Returns whether the proposal was adopted
+	pub fn validate_and_adopt_coordinate(&mut self, node_idx: NodeIdx, self_route_coord: RouteCoord, remote_route_coord: RouteCoord) -> Result<bool, NodeError> {
+		let (ticks, self_node_id) = (self.ticks, self.node_id);
+		let remote = self.remote_mut(node_idx)?;
+		let tracker = &remote.session()?.tracker;
+		if tracker.ping_count == 0 || !tracker.dist_avg.is_known() {
+			log::warn!("NodeID({}) can't validate coordinate proposal from NodeID({}) without a distance measurement", self_node_id, remote.node_id);
+			return Ok(false);
+		}
+		let measured = tracker.dist_avg as f64;
+		let proposed = types::route_dist(&self_route_coord, &remote_route_coord);
+		if (proposed - measured).abs() / measured.max(1.0) > remote::COORD_TOLERANCE {
+			log::warn!("NodeID({}) rejected coordinate proposal from NodeID({}): coordinates are {:.1} apart but measured distance is {:.1}", self_node_id, remote.node_id, proposed, measured);
+			return Ok(false);
+		}
+		remote.set_route_coord(Some(remote_route_coord), ticks);
+		self.set_self_route_coord(self_route_coord);
+		Ok(true)
+	}
+	/// Move this node to a new network address and tell all directly connected remotes so they can keep reaching it
+	pub fn set_net_addr(&mut self, new_net_addr: NetAddr, outgoing: &mut PacketVec) -> Result<(), NodeError> {
+		self.net_addr = new_net_addr;
+		let direct_remotes = self.sessions.right_values()
+			.filter(|&&node_idx| self.remote(node_idx).ok().and_then(|r| r.session().ok()).map_or(false, |s| s.direct().is_ok()))
+			.cloned().collect::<Vec<NodeIdx>>();
+		for node_idx in direct_remotes {
+			self.send_reliable(node_idx, NodePacket::AddressUpdate(new_net_addr), outgoing)?;
+		}
+		Ok(())
+	}
+	/// Set a remote's session, reconciling with any existing session to the same remote.
+	/// If both nodes handshake at the same time each ends up with two SessionIDs, both sides keep the lower one and send a Disconnect over the other.
+	/// Tracker history, peer status and setup metrics are carried over to the kept session
+	fn install_session(&mut self, node_idx: NodeIdx, mut session: RemoteSession, outgoing: &mut PacketVec) -> Result<(), NodeError> {
+		session.tracker.set_window(self.config.ping_window)?;
+		let remote = self.remote_mut(node_idx)?;
+		let node_id = remote.node_id;
+		let losing = match remote.session.take() {
+			Some(mut existing) if existing.session_id != session.session_id => {
+				log::debug!("NodeID({}) has duplicate sessions {} and {}, keeping lower", node_id, existing.session_id, session.session_id);
+				if existing.session_id < session.session_id {
+					existing.tracker.absorb_pending(std::mem::replace(&mut session.tracker, SessionTracker::new()));
+					std::mem::swap(&mut existing, &mut session);
+				} else {
+					std::mem::swap(&mut existing.tracker, &mut session.tracker);
+					session.tracker.absorb_pending(std::mem::replace(&mut existing.tracker, SessionTracker::new()));
+					if let (Ok(old), Ok(new)) = (existing.direct(), session.direct_mut()) {
+						new.peer_status = old.peer_status;
+					}
+				}
+				// `session` is kept and `existing` is the duplicate
+				session.absorb_duplicate(&existing);
+				Some(existing)
+			}
+			_ => None,
+		};
+		let session_id = session.session_id;
+		self.remote_mut(node_idx)?.session = Some(session);
+		self.sessions.insert(session_id, node_idx);
+		if let Some(losing) = losing {
+			self.sessions.remove_by_left(&losing.session_id);
+			self.retired_sessions.push_back(losing.session_id);
+			while self.retired_sessions.len() > MAX_RETIRED_SESSIONS { self.retired_sessions.pop_front(); }
+			// Remote may not have noticed the duplicate yet, tell it to close the losing session
+			outgoing.push(losing.gen_packet(losing.wrap_session(NodePacket::Disconnect), self)?);
+		}
+		Ok(())
+	}
+	/// Inject an externally measured round trip time to a remote as if a ping had completed, to seed coordinates from existing latency data.
+	/// If there is no session with the remote, only the route_map is updated
+	pub fn apply_latency_hint(&mut self, node_id: NodeID, rtt_ticks: usize) -> Result<(), NodeError> {
+		let self_ticks = self.ticks;
+		let ping_id = self.gen_random();
+		let (node_idx, remote) = self.add_remote(node_id)?;
+		let distance = if let Some(session) = remote.session.as_mut() {
+			let ping_id = session.tracker.gen_ping(ping_id, self_ticks.saturating_sub(rtt_ticks));
+			let distance = session.tracker.acknowledge_ping(ping_id, self_ticks)?;
+			self.set_direct_dist(node_idx, node_id, distance);
+			distance
+		} else {
+			types::scalar_from_f64(rtt_ticks as f64 / 2.0)
+		};
+		self.record_edge(self.node_id, node_id, distance);
+		Ok(())
+	}
+	/// Record the latest measured distance of a direct session, replacing its previous entry in direct_sorted
+	fn set_direct_dist(&mut self, node_idx: NodeIdx, node_id: NodeID, distance: RouteScalar) {
+		let previous = self.remote_mut(node_idx).ok().and_then(|remote| remote.direct_dist.replace(distance));
+		if let Some(previous) = previous { self.direct_sorted.remove(&(previous, node_id)); }
+		self.direct_sorted.insert((distance, node_id), node_idx);
+	}
+	/// Tear down the session with a remote and tombstone it so it isn't automatically reconnected to right away
+	pub fn close_session(&mut self, node_idx: NodeIdx) -> Result<(), NodeError> {
+		let cooldown = self.config.tombstone_cooldown;
+		let ticks = self.ticks;
+		let remote = self.remote_mut(node_idx)?;
+		let node_id = remote.node_id;
+		if let Some(net_addr) = remote.session.as_ref().and_then(|s| s.direct().ok()).map(|d| d.net_addr) {
+			remote.last_net_addr = Some(net_addr);
+			remote.last_seen = Some(ticks);
+		}
+		remote.session = None;
+		remote.pending_session = None;
+		let direct_dist = remote.direct_dist.take();
+		self.sessions.remove_by_right(&node_idx);
+		if let Some(dist) = direct_dist { self.direct_sorted.remove(&(dist, node_id)); }
+		self.peer_list.remove_by_left(&node_idx);
+		self.tombstones.insert(node_id, ticks + cooldown);
+		log::debug!("[{: >6}] NodeID({}) closed session with NodeID({})", ticks, self.node_id, node_id);
+		Ok(())
+	}
+	fn record_relay_client(&mut self, node_id: NodeID) {
+		if !self.relay_clients.contains_key(&node_id) && self.relay_clients.len() >= MAX_RELAY_CLIENTS {
+			if let Some(least_active) = self.relay_clients.iter().min_by_key(|(_, &count)| count).map(|(id, _)| *id) {
+				self.relay_clients.remove(&least_active);
+			}
+		}
+		*self.relay_clients.entry(node_id).or_insert(0) += 1;
+	}
+	/// Remotes that have used this node as a hop for traversed packets, and how many packets were forwarded for each
+	pub fn relay_clients(&self) -> &HashMap<NodeID, usize> {
+		&self.relay_clients
+	}
+	/// Clear peer status in both directions and remove remote from peer_list, session is left intact.
+	/// The remote isn't selected as a peer again until `reenable_peer` is called
+	fn release_peer(&mut self, node_idx: NodeIdx) -> Result<(), NodeError> {
+		let remote = self.remote_mut(node_idx)?;
+		let node_id = remote.node_id;
+		let direct = remote.session_mut()?.direct_mut()?;
+		direct.set_peer(false);
+		direct.record_peer_drop();
+		self.peer_list.remove_by_left(&node_idx);
+		self.released_peers.insert(node_id);
+		Ok(())
+	}
+	/// Let a remote released with Bye be selected as a peer again
+	pub fn reenable_peer(&mut self, node_id: NodeID) {
+		if self.released_peers.remove(&node_id) && self.route_coord.is_some() { self.action(NodeAction::CalculatePeers) }
+	}
+	pub fn is_released(&self, node_id: &NodeID) -> bool {
+		self.released_peers.contains(node_id)
+	}
+	/// Always keep a remote in peer_list regardless of peer selection, once it has a direct session and a coordinate.
+	/// If there is no session, one is established to the address the remote was last seen at
+	pub fn pin_peer(&mut self, node_id: NodeID) {
+		let last_net_addr = self.index_by_node_id(&node_id).ok().and_then(|node_idx| self.remote(node_idx).ok()).and_then(|remote| remote.last_net_addr);
+		match last_net_addr {
+			Some(net_addr) => self.pin_peer_at(node_id, net_addr),
+			None => {
+				if !self.has_session_or_pending(&node_id) {
+					log::warn!("NodeID({}) pinned NodeID({}) without a session or known address, it will be a peer once connected", self.node_id, node_id);
+				}
+				self.pinned_peers.insert(node_id);
+				self.released_peers.remove(&node_id);
+				if self.route_coord.is_some() { self.action(NodeAction::CalculatePeers) }
+			}
+		}
+	}
+	/// Pin a remote like `pin_peer`, connecting to it at `net_addr` if there is no session with it yet
+	pub fn pin_peer_at(&mut self, node_id: NodeID, net_addr: NetAddr) {
+		self.pinned_peers.insert(node_id);
+		self.released_peers.remove(&node_id);
+		if !self.has_session_or_pending(&node_id) {
+			// CalculatePeers runs again once the session's info exchange arrives
+			self.connect_to(node_id, net_addr);
+		} else if self.route_coord.is_some() {
+			self.action(NodeAction::CalculatePeers);
+		}
+	}
+	fn has_session_or_pending(&self, node_id: &NodeID) -> bool {
+		self.index_by_node_id(node_id).ok().and_then(|node_idx| self.remote(node_idx).ok())
+			.map_or(false, |remote| remote.session.is_some() || remote.pending_session.is_some())
+	}
+	/// Let a pinned remote be selected or evicted normally again
+	pub fn unpin_peer(&mut self, node_id: NodeID) {
+		self.pinned_peers.remove(&node_id);
+		if self.route_coord.is_some() { self.action(NodeAction::CalculatePeers) }
+	}
+	pub fn is_pinned(&self, node_id: &NodeID) -> bool {
+		self.pinned_peers.contains(node_id)
+	}
+	/// True if session with this node was recently closed and automatic reconnects should be suppressed
+	pub fn is_tombstoned(&self, node_id: &NodeID) -> bool {
+		self.tombstones.get(node_id).map_or(false, |&expiry| self.ticks < expiry)
+	}
+
+	/// Queue a direct connection to a remote that exchanges info once established, like Bootstrap but without starting self-organization.
+	/// Returns the NodeID to pass to `is_connected`
+	pub fn connect_to(&mut self, node_id: NodeID, net_addr: NetAddr) -> NodeID {
+		self.action(NodeAction::Connect(
+			node_id,
+			SessionType::direct(net_addr),
+			vec![NodePacket::ExchangeInfo(self.route_coord, 0, 0)],
+		));
+		node_id
+	}
+	/// Returns true if both this node and the remote consider each other peers
+	pub fn is_mutual_peer(&self, node_id: &NodeID) -> bool {
+		self.index_by_node_id(node_id)
+			.and_then(|node_idx| self.remote(node_idx))
+			.map_or(false, |remote| remote.session.as_ref().map_or(false, |s| s.is_mutual_peer()))
+	}
+	/// List remotes that have peered with this node without this node peering with them, sorted by NodeID
+	pub fn incoming_peers(&self) -> Vec<NodeID> {
+		let mut incoming = self.remotes.values()
+			.filter(|r| r.session().ok().and_then(|s| s.direct().ok()).map_or(false, |d| d.is_incoming_only()))
+			.map(|r| r.node_id)
+			.collect::<Vec<NodeID>>();
+		incoming.sort_unstable();
+		incoming
+	}
+	/// List all mutual peers of this node
+	pub fn mutual_peers(&self) -> Vec<NodeID> {
+		self.peer_list.left_values()
+			.filter_map(|&node_idx| self.remotes.get(node_idx))
+			.filter(|remote| remote.session.as_ref().map_or(false, |s| s.is_mutual_peer()))
+			.map(|remote| remote.node_id)
+			.collect()
+	}
+	/// Returns true if there is an active session with a remote
+	pub fn is_connected(&self, node_id: &NodeID) -> bool {
+		self.index_by_node_id(node_id)
+			.and_then(|node_idx| self.remote(node_idx))
+			.map_or(false, |remote| remote.session_active())
+	}
+
 	/// Initiate handshake process and send packets when completed
 	pub fn connect(
 		&mut self,
@@ -839,7 +2328,7 @@ impl Node {
 		initial_packets: Vec<NodePacket>,
 		outgoing: &mut PacketVec,
 	) -> Result<(), NodeError> {
-		let session_id: SessionID = rand::random(); // Create random session ID
+		let session_id: SessionID = self.gen_random(); // Create random session ID
 											//let self_node_id = self.node_id;
 		let self_ticks = self.ticks;
 		let self_node_id = self.node_id;
@@ -860,8 +2349,12 @@ impl Node {
 		// TODO: actual cryptography
 		match session_type {
 			SessionType::Direct(direct) => {
-				// Directly send
-				outgoing.push(encryption.package(direct.net_addr));
+				// Directly send, resending until acknowledged since a lost handshake would otherwise never be recovered
+				outgoing.push(encryption.package(direct.net_addr, self.config.encryption));
+				if self.config.handshake_retransmit_interval != 0 {
+					let retransmit = self.schedule_handshake_retransmit(dest_node_id, session_id, 0);
+					self.action(retransmit);
+				}
 			}
 			SessionType::Traversed(traversal) => {
 				// Send traversed through closest peer
@@ -873,11 +2366,23 @@ impl Node {
 					outgoing,
 				)?;
 			}
-			_ => unimplemented!(),
+			routed @ SessionType::Routed(_) => {
+				// Wrap the handshake through every proxy the same way packets of the established session will be
+				let packet = RemoteSession::new(session_id, routed).gen_packet(encryption, self)?;
+				outgoing.push(packet);
+			}
 		}
 
 		Ok(())
 	}
+	/// RetransmitHandshake delayed by an exponentially growing, jittered interval so nodes that lost packets at the same time don't retry in lockstep
+	fn schedule_handshake_retransmit(&self, dest_node_id: NodeID, session_id: SessionID, retransmits: usize) -> NodeAction {
+		let backoff = (1usize << retransmits.min(16)).min(MAX_HANDSHAKE_BACKOFF);
+		let delay = self.config.handshake_retransmit_interval * backoff;
+		let jitter = (delay as f64 * 0.5 * self.gen_random::<f64>()) as usize;
+		NodeAction::RetransmitHandshake(dest_node_id, session_id, retransmits)
+			.gen_condition(NodeActionCondition::RunAt(self.ticks + delay + jitter))
+	}
 	// Create multiple Routed Sessions that sequentially resolve their pending_route fields as Traversed Packets are acknowledged
 	/* fn routed_connect(&mut self, dest_node_id: NodeID, outgoing: &mut PacketVec) {
 		//let routed_session_id: SessionID = rand::random();
@@ -903,13 +2408,35 @@ impl Node {
 		if let Some(request) = received_packet.request {
 			match request {
 				InternetRequest::RouteCoordDHTReadResponse(query_node_id, route_option) => {
+					self.pending_dht.remove(&query_node_id);
+					// Reading this node's own id (e.g. a stale request) must not create a remote for itself, only check that the DHT is current
+					if query_node_id == self.node_id {
+						if route_option != self.public_route {
+							log::warn!("NodeID({}) DHT holds {:?} for this node but it published {:?}", self.node_id, route_option, self.public_route);
+							self.public_route = None; // Republish on the next CalculatePeers
+						}
+						return Ok(None);
+					}
 					if let Some(query_route_coord) = route_option {
+						let self_ticks = self.ticks;
 						let (_, remote) = self.add_remote(query_node_id)?;
-						remote.route_coord.get_or_insert(query_route_coord);
+						if remote.route_coord.is_none() {
+							remote.set_route_coord(Some(query_route_coord), self_ticks);
+						}
 					} else {
 						log::warn!("No Route Coordinate found for: {:?}", query_node_id);
 					}
 				}
+				InternetRequest::RouteCoordDHTRegionResponse(_, entries) => {
+					let self_ticks = self.ticks;
+					for (node_id, route_coord) in entries {
+						if node_id == self.node_id { continue }
+						let (_, remote) = self.add_remote(node_id)?;
+						if remote.route_coord.is_none() {
+							remote.set_route_coord(Some(route_coord), self_ticks);
+						}
+					}
+				}
 				InternetRequest::RouteCoordDHTWriteResponse(_) => {}
 				_ => {
 					log::warn!("Not a InternetRequest Response variant")
@@ -918,12 +2445,23 @@ impl Node {
 			return Ok(None);
 		}
 
-		let encryption = NodeEncryption::unpackage(&received_packet)?;
-		self.parse_node_encryption(
+		let received_bytes = received_packet.data.len();
+		// Symmetric packets can't be decrypted until the handshake that establishes their session key completes
+		if let Some(session_id) = NodeEncryption::session_key_id(&received_packet) {
+			if !self.sessions.contains_left(&session_id) && !self.retired_sessions.contains(&session_id) {
+				return Err(NodeError::SessionKeyNotEstablished { session_id });
+			}
+		}
+		let encryption = NodeEncryption::unpackage(&received_packet, self.config.encryption)?;
+		let parsed = self.parse_node_encryption(
 			encryption,
 			SessionType::direct(received_packet.src_addr),
 			outgoing,
-		)
+		)?;
+		if let Some(session) = parsed.as_ref().and_then(|(node_idx, _)| self.remote(*node_idx).ok()?.session().ok()) {
+			session.record_received(received_bytes);
+		}
+		Ok(parsed)
 	}
 	fn parse_node_encryption(
 		&mut self,
@@ -944,7 +2482,16 @@ impl Node {
 				if recipient != self.node_id {
 					Err(RemoteNodeError::UnknownAckRecipient { recipient })?;
 				}
+				let return_ping_id = self.gen_random();
 				let (remote_idx, remote) = self.add_remote(signer)?;
+				// Retransmitted handshake for an established session means the Acknowledge was lost, resend it without resetting the session
+				if remote.session.as_ref().map_or(false, |session| session.session_id == session_id) {
+					remote.session.as_mut().unwrap().tracker.gen_ping(return_ping_id, self_ticks);
+					let acknowledgement = NodeEncryption::Acknowledge { session_id, acknowledger: recipient, return_ping_id };
+					let packet = self.remote(remote_idx)?.session()?.gen_packet(acknowledgement, self)?;
+					outgoing.push(packet);
+					return Ok(None);
+				}
 				// Check if there is not already a pending session
 				if remote.pending_session.is_some() {
 					if self_node_id < remote.node_id {
@@ -953,7 +2500,8 @@ impl Node {
 				}
 
 				let mut session = RemoteSession::new(session_id, return_session_type);
-				let return_ping_id = session.tracker.gen_ping(self_ticks);
+				session.start_setup(self_ticks);
+				session.tracker.gen_ping(return_ping_id, self_ticks);
 				let acknowledgement = NodeEncryption::Acknowledge {
 					session_id,
 					acknowledger: recipient,
@@ -961,9 +2509,7 @@ impl Node {
 				};
 				let packet = session.gen_packet(acknowledgement, self)?;
 				outgoing.push(packet);
-				self.remote_mut(remote_idx)?.session = Some(session);
-
-				self.sessions.insert(session_id, remote_idx);
+				self.install_session(remote_idx, session, outgoing)?;
 				log::debug!(
 					"[{: >6}] Node({:?}) Received Handshake: {:?}",
 					self_ticks,
@@ -977,21 +2523,25 @@ impl Node {
 				acknowledger,
 				return_ping_id,
 			} => {
+				let ping_id = self.gen_random();
 				let remote_idx = self.index_by_node_id(&acknowledger)?;
 				let mut remote = self.remote_mut(remote_idx)?;
-				if let Some(boxed_pending) = remote.pending_session.take() {
-					let (
-						pending_session_id,
-						time_sent_handshake,
-						packets_to_send,
-						pending_session_type,
-					) = *boxed_pending;
+				if let Some(pending_session_id) = remote.pending_session.as_ref().map(|p| p.0) {
+					// Only take the pending handshake once the ack is known to be for it, so a bogus ack can't cancel it
 					if pending_session_id == session_id {
-						// Create session and acknowledge out-of-tracker ping
-						let mut session = RemoteSession::new(session_id, pending_session_type);
-						let ping_id = session.tracker.gen_ping(time_sent_handshake);
+						let (
+							pending_session_id,
+							time_sent_handshake,
+							packets_to_send,
+							pending_session_type,
+						) = *remote.pending_session.take().unwrap();
+						// Create session under the id this node chose, never one taken from the packet
+						let mut session = RemoteSession::new(pending_session_id, pending_session_type);
+						session.start_setup(time_sent_handshake);
+						session.finish_setup(self_ticks);
+						let ping_id = session.tracker.gen_ping(ping_id, time_sent_handshake);
 						let distance = session.tracker.acknowledge_ping(ping_id, self_ticks)?;
-						remote.session = Some(session); // update remote
+						self.install_session(remote_idx, session, outgoing)?; // update remote
 
 						// Update packets
 						let packets_to_send =
@@ -1003,11 +2553,10 @@ impl Node {
 							NodePacket::ConnectionInit(return_ping_id, packets_to_send),
 							outgoing,
 						)?;
+						self.announce_anchor(remote_idx, outgoing)?;
 						// Make note of session
-						self.sessions.insert(session_id, remote_idx);
-						self.direct_sorted.insert(distance, remote_idx);
-						self.route_map
-							.add_edge(self.node_id, acknowledger, distance);
+						self.set_direct_dist(remote_idx, acknowledger, distance);
+						self.record_edge(self.node_id, acknowledger, distance);
 
 						log::debug!(
 							"[{: >6}] Node({:?}) Received Acknowledgement: {:?}",
@@ -1019,13 +2568,47 @@ impl Node {
 					} else {
 						Err(RemoteNodeError::UnknownAck { passed: session_id })?
 					}
+				} else if remote.session.as_ref().map_or(false, |s| s.session_id == session_id) {
+					// Retransmitted acknowledgement for a session that is already established
+					log::debug!("[{: >6}] Node({:?}) Ignoring duplicate Acknowledgement for SessionID({})", self_ticks, self_node_id, session_id);
+					None
 				} else {
 					Err(RemoteNodeError::NoPendingHandshake)?
 				}
 			}
 			NodeEncryption::Session { session_id, packet } => {
+				// Packets (like the Disconnect) still in flight on a session replaced by a duplicate are dropped
+				if self.retired_sessions.contains(&session_id) { return Ok(None) }
 				Some((self.index_by_session_id(&session_id)?, packet))
 			}
+			NodeEncryption::Notify { recipient, data, sender } => {
+				if recipient != self.node_id {
+					Err(RemoteNodeError::UnknownAckRecipient { recipient })?;
+				}
+				if self.reachability_probe == Some(data) {
+					log::debug!("NodeID({}) received reachability connect-back from NodeID({})", self_node_id, sender);
+					self.reachability_probe = None;
+					self.reachable = Some(true);
+				} else {
+					self.events.push(NodeEvent::Notify { sender, data });
+				}
+				None
+			}
+			NodeEncryption::Error { recipient, sender, code, context } => {
+				if recipient != self.node_id {
+					Err(RemoteNodeError::UnknownAckRecipient { recipient })?;
+				}
+				log::warn!("[{: >6}] NodeID({}) packet was rejected by NodeID({}): {:?} {}", self_ticks, self_node_id, sender, code, context);
+				self.events.push(NodeEvent::ProtocolError { sender, code, context });
+				None
+			}
+			NodeEncryption::TraversalAck { recipient, acknowledger, sent_at } => {
+				if recipient != self.node_id {
+					Err(RemoteNodeError::UnknownAckRecipient { recipient })?;
+				}
+				self.events.push(NodeEvent::TraversalAck { acknowledger, latency: self_ticks.saturating_sub(sent_at) });
+				None
+			}
 			_ => {
 				unimplemented!();
 			}
@@ -1047,6 +2630,89 @@ impl Node {
 			})
 			.collect::<Vec<NodePacket>>())
 	}
+	/// Send a packet that is retransmitted every `ack_timeout` ticks until the remote acknowledges it
+	pub fn send_reliable(&mut self, node_idx: NodeIdx, packet: NodePacket, outgoing: &mut PacketVec) -> Result<(), NodeError> {
+		let request_id: u64 = self.gen_random();
+		let self_ticks = self.ticks;
+		self.remote_mut(node_idx)?.session_mut()?.pending_acks.insert(request_id, session::PendingAck { packet: packet.clone(), sent_at: self_ticks, attempts: 1 });
+		self.send_packet(node_idx, NodePacket::Reliable(request_id, Box::new(packet)), outgoing)
+	}
+	/// Retransmit Reliable packets that haven't been acknowledged in time, giving up after `ack_attempts`
+	fn retransmit_unacked(&mut self, outgoing: &mut PacketVec) {
+		let (self_ticks, timeout, max_attempts) = (self.ticks, self.config.ack_timeout, self.config.ack_attempts);
+		let mut resend = Vec::new();
+		for (node_idx, remote) in self.remotes.iter_mut() {
+			let node_id = remote.node_id;
+			let session = match remote.session.as_mut() { Some(session) => session, None => continue };
+			session.pending_acks.retain(|&request_id, pending| {
+				if self_ticks.saturating_sub(pending.sent_at) < timeout { return true }
+				if pending.attempts >= max_attempts {
+					log::debug!("NodeID({}) gave up on Reliable request {} after {} attempts", node_id, request_id, pending.attempts);
+					return false
+				}
+				pending.sent_at = self_ticks;
+				pending.attempts += 1;
+				resend.push((node_idx, NodePacket::Reliable(request_id, Box::new(pending.packet.clone()))));
+				true
+			});
+		}
+		for (node_idx, packet) in resend {
+			if let Err(err) = self.send_packet(node_idx, packet, outgoing) {
+				log::error!("NodeID({}) failed to retransmit Reliable packet: {:?}", self.node_id, err);
+			}
+		}
+	}
+	/// Retry DHT reads that timed out, and once out of attempts give up on them and the actions waiting for their result
+	fn check_dht_timeouts(&mut self, outgoing: &mut PacketVec) {
+		let ticks = self.ticks;
+		let expired = self.pending_dht.iter()
+			.filter(|(_, &(deadline, _))| ticks >= deadline)
+			.map(|(&node_id, &(_, attempts))| (node_id, attempts))
+			.collect::<Vec<(NodeID, usize)>>();
+		for (node_id, attempts) in expired {
+			if attempts < self.config.dht_attempts {
+				log::debug!("[{: >6}] NodeID({}) DHT lookup of NodeID({}) timed out, retrying", ticks, self.node_id, node_id);
+				outgoing.push(InternetPacket::gen_request(self.net_addr, InternetRequest::RouteCoordDHTRead(node_id)));
+				self.pending_dht.insert(node_id, (ticks + self.config.dht_timeout, attempts + 1));
+			} else {
+				log::warn!("[{: >6}] NodeID({}) DHT lookup of NodeID({}) failed after {} attempts", ticks, self.node_id, node_id, attempts);
+				self.pending_dht.remove(&node_id);
+				self.action_list.retain(|action| !matches!(action, NodeAction::Condition(NodeActionCondition::RemoteRouteCoord(waiting), _) if *waiting == node_id));
+				self.events.push(NodeEvent::RouteCoordLookupFailed { node_id });
+			}
+		}
+	}
+	/// (coordinate, measured distance to remote) of this node and mutual neighbors of a remote, used to check the remote's claimed coordinate
+	fn coord_anchors(&self, node_idx: NodeIdx) -> Result<Vec<(RouteCoord, f64)>, NodeError> {
+		use petgraph::Direction::{Incoming, Outgoing};
+		let remote = self.remote(node_idx)?;
+		let mut anchors = Vec::new();
+		if let (Some(self_route_coord), Ok(session)) = (self.route_coord, remote.session()) {
+			if session.tracker.ping_count > 0 { anchors.push((self_route_coord, session.dist() as f64)) }
+		}
+		let neighbors = self.route_map.neighbors_directed(remote.node_id, Outgoing)
+			.chain(self.route_map.neighbors_directed(remote.node_id, Incoming))
+			.filter(|&neighbor_id| neighbor_id != self.node_id)
+			.collect::<HashSet<NodeID>>();
+		for neighbor_id in neighbors {
+			let dist = match self.route_map.edge_weight(remote.node_id, neighbor_id).or(self.route_map.edge_weight(neighbor_id, remote.node_id)) {
+				Some(edge) if edge.dist.is_known() => edge.dist as f64,
+				_ => continue,
+			};
+			let anchor = self.index_by_node_id(&neighbor_id).ok().and_then(|idx| self.remote(idx).ok());
+			if let Some(anchor_coord) = anchor.filter(|anchor| anchor.coord_trusted).and_then(|anchor| anchor.route_coord) {
+				anchors.push((anchor_coord, dist));
+			}
+		}
+		Ok(anchors)
+	}
+	/// Tell a remote that just set up a session with this node that this node is an anchor, if it is one
+	fn announce_anchor(&self, node_idx: NodeIdx, outgoing: &mut PacketVec) -> Result<(), NodeError> {
+		match self.route_coord {
+			Some(route_coord) if self.anchor => self.send_packet(node_idx, NodePacket::Anchor(Some(route_coord)), outgoing),
+			_ => Ok(()),
+		}
+	}
 	fn send_packet(
 		&self,
 		node_idx: NodeIdx,
@@ -1058,11 +2724,270 @@ impl Node {
 		outgoing.push(packet);
 		Ok(())
 	}
+	/// Translate all known coordinates so that their centroid is near origin if they have drifted too far.
+	/// Relative distances are preserved.
+	pub fn normalize_coordinates(&mut self) {
+		// Anchors already hold the coordinate frame in place
+		if self.anchor || self.remotes.values().any(|r| r.is_anchor) { return }
+		let coords = self.route_coord.iter().chain(self.remotes.values().filter_map(|r| r.route_coord.as_ref()));
+		let (sum, count) = coords.fold((Vector2::new(0.0, 0.0), 0usize), |(sum, count), c| (sum + c.as_f64().coords, count + 1));
+		if count == 0 { return }
+		let centroid = RouteCoord::from_f64(Point::from(sum / count as f64)).coords;
+		if centroid.x.abs() < COORD_RECENTER_THRESHOLD && centroid.y.abs() < COORD_RECENTER_THRESHOLD { return }
+
+		log::debug!("NodeID({}) recentering coordinates by ({}, {})", self.node_id, centroid.x, centroid.y);
+		if let Some(route_coord) = &mut self.route_coord { *route_coord -= centroid; }
+		for (_, remote) in self.remotes.iter_mut() {
+			if let Some(route_coord) = &mut remote.route_coord { *route_coord -= centroid; }
+			if let Some(session) = &mut remote.session {
+				match &mut session.session_type {
+					SessionType::Traversed(traversed) => traversed.route_coord -= centroid,
+					SessionType::Routed(routed) => routed.route_coord -= centroid,
+					SessionType::Direct(_) => {}
+				}
+			}
+		}
+		self.peer_list = self.peer_list.iter().map(|(&node_idx, &route_coord)| (node_idx, route_coord - centroid)).collect();
+	}
+	/// Record a distance measurement between two nodes, blending it with any previous measurement
+	fn record_edge(&mut self, from: NodeID, to: NodeID, dist: RouteScalar) {
+		self.blend_edge(from, to, dist, self.config.edge_decay);
+	}
+	/// Blend a distance into a route_map edge with weight `decay`, creating the edge if it doesn't exist
+	fn blend_edge(&mut self, from: NodeID, to: NodeID, dist: RouteScalar, decay: f64) {
+		// Remote may report a link it hasn't measured yet
+		if !dist.is_known() { return }
+		let ticks = self.ticks;
+		let decay = decay.max(0.0).min(1.0);
+		if let Some(edge) = self.route_map.edge_weight_mut(from, to) {
+			edge.dist = (edge.dist as f64 * (1.0 - decay) + dist as f64 * decay).round() as RouteScalar;
+			edge.last_updated = ticks;
+		} else {
+			self.route_map.add_edge(from, to, RouteEdge { dist, last_updated: ticks });
+		}
+	}
+	fn prune_routes(&mut self) {
+		let mut reachable = HashSet::new();
+		if self.route_map.contains_node(self.node_id) {
+			let mut dfs = petgraph::visit::Dfs::new(&self.route_map, self.node_id);
+			while let Some(node_id) = dfs.next(&self.route_map) {
+				reachable.insert(node_id);
+			}
+		}
+		let unreachable = self.route_map.nodes().filter(|node_id| !reachable.contains(node_id)).collect::<Vec<NodeID>>();
+		for node_id in unreachable {
+			self.route_map.remove_node(node_id);
+		}
+
+		// Remotes with a known coordinate (e.g. from the DHT or gossip) or that queued actions refer to are still useful
+		let mut referenced = HashSet::new();
+		self.action_list.iter().for_each(|action| action.referenced_nodes(&mut referenced));
+		let stale = self.remotes.iter()
+			.filter(|(_, remote)| remote.session.is_none() && remote.pending_session.is_none() && remote.route_coord.is_none())
+			.filter(|(_, remote)| !reachable.contains(&remote.node_id) && !referenced.contains(&remote.node_id))
+			.map(|(node_idx, _)| node_idx)
+			.collect::<Vec<NodeIdx>>();
+		for node_idx in stale {
+			if let Some(remote) = self.remotes.remove(node_idx) {
+				self.ids.remove_by_left(&remote.node_id);
+			}
+		}
+		log::debug!("[{: >6}] NodeID({}) pruned routes, {} nodes remain in route_map", self.ticks, self.node_id, self.route_map.node_count());
+	}
+	/// Evict route_map nodes until there are at most `config.max_route_map_nodes`.
+	/// Peers, pinned peers and anchor remotes (see `set_anchor`) are always kept, then nodes are evicted from furthest hop count to nearest so kept nodes stay connected, least used relay hops first
+	fn cap_route_map(&mut self) {
+		let cap = self.config.max_route_map_nodes;
+		if cap == 0 || self.route_map.node_count() <= cap { return }
+
+		let mut protected = self.remotes.values()
+			.filter(|remote| remote.is_anchor || self.pinned_peers.contains(&remote.node_id))
+			.map(|remote| remote.node_id)
+			.collect::<HashSet<NodeID>>();
+		protected.extend(self.peer_list.left_values().filter_map(|&node_idx| self.remote(node_idx).ok()).map(|r| r.node_id));
+		protected.insert(self.node_id);
+
+		// Hop count from this node
+		let mut depths = HashMap::new();
+		let mut queue = VecDeque::new();
+		if self.route_map.contains_node(self.node_id) {
+			depths.insert(self.node_id, 0usize);
+			queue.push_back(self.node_id);
+		}
+		while let Some(node_id) = queue.pop_front() {
+			let depth = depths[&node_id];
+			for neighbor in self.route_map.neighbors(node_id) {
+				if !depths.contains_key(&neighbor) {
+					depths.insert(neighbor, depth + 1);
+					queue.push_back(neighbor);
+				}
+			}
+		}
+
+		let mut candidates = self.route_map.nodes()
+			.filter(|node_id| !protected.contains(node_id))
+			.map(|node_id| {
+				let depth = depths.get(&node_id).cloned().unwrap_or(usize::MAX);
+				let usage = self.relay_clients.get(&node_id).cloned().unwrap_or(0);
+				(std::cmp::Reverse(depth), usage, node_id)
+			})
+			.collect::<Vec<(std::cmp::Reverse<usize>, usize, NodeID)>>();
+		candidates.sort_unstable();
+		let excess = self.route_map.node_count() - cap;
+		for (_, _, node_id) in candidates.into_iter().take(excess) {
+			self.route_map.remove_node(node_id);
+		}
+		log::debug!("[{: >6}] NodeID({}) capped route_map to {} nodes", self.ticks, self.node_id, self.route_map.node_count());
+	}
+	/// Watchdog that reconnects to the most recently seen remotes once every session has been lost, backing off exponentially while isolated
+	fn check_isolation(&mut self) {
+		let interval = self.config.reconnect_interval;
+		if interval == 0 { return }
+		let ticks = self.ticks;
+		if self.remotes.values().any(|r| r.session.is_some()) { self.reconnect_backoff = 0; return }
+		// Wait on handshakes still in flight (including this watchdog's own) without resetting the backoff, ones unanswered for a whole interval don't count
+		let connecting = self.remotes.values().any(|r| r.pending_session.as_ref().map_or(false, |p| ticks < p.1 + interval));
+		if connecting { return }
+		if ticks < self.next_reconnect { return }
+
+		let mut candidates = self.remotes.values()
+			.filter(|r| !self.is_tombstoned(&r.node_id))
+			.filter_map(|r| Some((r.last_seen?, r.node_id, r.last_net_addr?)))
+			.collect::<Vec<(usize, NodeID, NetAddr)>>();
+		if candidates.is_empty() { return }
+		candidates.sort_unstable_by_key(|&(last_seen, node_id, _)| (std::cmp::Reverse(last_seen), node_id));
+		for &(_, node_id, net_addr) in candidates.iter().take(RECONNECT_ATTEMPTS) {
+			log::info!("[{: >6}] NodeID({}) lost every session, reconnecting to NodeID({}) at {}", ticks, self.node_id, node_id, net_addr);
+			self.action(NodeAction::Connect(node_id, SessionType::direct(net_addr), vec![NodePacket::ExchangeInfo(self.route_coord, 0, 0)]));
+		}
+		self.reconnect_backoff = (self.reconnect_backoff * 2).max(1).min(MAX_RECONNECT_BACKOFF);
+		self.next_reconnect = ticks + interval * self.reconnect_backoff;
+	}
+	/// Periodic maintenance run every `sweep_interval` ticks
+	fn sweep(&mut self) {
+		let ticks = self.ticks;
+		self.tombstones.retain(|_, &mut expiry| ticks < expiry);
+
+		// Demote peers that are dropping too many pings
+		let max_loss_rate = self.config.max_peer_loss_rate;
+		let lossy_peer = self.peer_list.left_values().any(|&node_idx| {
+			self.remote(node_idx).ok().and_then(|r| r.session().ok()).map_or(false, |s| s.tracker.loss_rate() > max_loss_rate)
+		});
+		if lossy_peer {
+			self.action_list.push(NodeAction::CalculatePeers);
+		}
+		if self.config.recalc_interval != 0 && self.coord_age(self.node_id).map_or(false, |age| age >= self.config.recalc_interval) {
+			self.action_list.push(NodeAction::CalcRouteCoord);
+		}
+		if self.config.gossip_coords && !self.peer_list.is_empty() {
+			self.action_list.push(NodeAction::GossipCoords);
+		}
+
+		// Prune route_map edges that haven't been measured in a while
+		let (ticks, expiry) = (self.ticks, self.config.edge_expiry);
+		let stale = self.route_map.all_edges()
+			.filter(|(_, _, edge)| ticks.saturating_sub(edge.last_updated) > expiry)
+			.map(|(from, to, _)| (from, to))
+			.collect::<Vec<(NodeID, NodeID)>>();
+		for (from, to) in stale {
+			self.route_map.remove_edge(from, to);
+		}
+		self.cap_route_map();
+	}
+	fn initial_route_coord(&self, remote_route_coord: Option<RouteCoord>, remote_dist: RouteScalar) -> Option<RouteCoord> {
+		match self.config.coord_init {
+			CoordInit::Deferred => None,
+			CoordInit::Origin => Some(RouteCoord::origin()),
+			CoordInit::NearBootstrap => {
+				let remote_route_coord = remote_route_coord?;
+				let radius = if remote_dist.is_known() { remote_dist.max(1) as f64 } else { 1.0 };
+				let angle = self.gen_random::<f64>() * std::f64::consts::TAU;
+				let offset = Vector2::new(angle.cos() * radius, angle.sin() * radius);
+				Some(RouteCoord::from_f64(remote_route_coord.as_f64() + offset))
+			}
+		}
+	}
+	/// Pin this node's coordinate so it is never recalculated, anchors fix the coordinate frame in place and other nodes weight them by `config.anchor_weight`
+	pub fn set_anchor(&mut self, route_coord: RouteCoord) {
+		self.anchor = true;
+		self.recent_coords.clear();
+		self.coord_frozen_until = None;
+		self.set_self_route_coord(route_coord);
+		self.broadcast_anchor(Some(route_coord));
+		self.action(NodeAction::CalculatePeers);
+	}
+	pub fn is_anchor(&self) -> bool { self.anchor }
+	/// Tell every direct remote that this node is (Some) or no longer is (None) an anchor
+	fn broadcast_anchor(&mut self, route_coord: Option<RouteCoord>) {
+		let direct_remotes = self.sessions.right_values()
+			.filter(|&&node_idx| self.remote(node_idx).ok().and_then(|r| r.session().ok()).map_or(false, |s| s.direct().is_ok()))
+			.cloned().collect::<Vec<NodeIdx>>();
+		let mut outgoing = PacketVec::new();
+		for node_idx in direct_remotes {
+			if let Err(err) = self.send_packet(node_idx, NodePacket::Anchor(route_coord), &mut outgoing) {
+				log::debug!("NodeID({}) failed to announce anchor status: {:?}", self.node_id, err);
+			}
+		}
+		self.queued_outgoing.extend(outgoing);
+	}
+	/// Throw away this node's coordinate and recalculate it from scratch using current neighbors, for recovering from a bad embedding.
+	/// Also unpins an anchor's coordinate
+	pub fn reset_coordinate(&mut self) {
+		if std::mem::take(&mut self.anchor) { self.broadcast_anchor(None) }
+		self.route_coord = None;
+		self.route_coord_updated = None;
+		self.route_anchor_count = 0;
+		self.recent_coords.clear();
+		self.coord_frozen_until = None;
+		if self.public_route.take().is_some() {
+			self.queued_outgoing.push(InternetPacket::gen_request(self.net_addr, InternetRequest::RouteCoordDHTDelete(self.node_id)));
+		}
+		self.last_published = None;
+		self.action(NodeAction::CalcRouteCoord);
+	}
+	/// Replace this node's config at runtime and re-derive the state that depends on it, nothing is changed if the new config is rejected
+	pub fn apply_config(&mut self, config: NodeConfig) -> Result<(), NodeError> {
+		if config.target_peer_count == 0 { Err(NodeError::InvalidConfig { reason: "target_peer_count must be at least 1" })? }
+		if config.ping_window == 0 { Err(NodeError::InvalidConfig { reason: "ping_window must be at least 1" })? }
+		if config.ack_attempts == 0 { Err(NodeError::InvalidConfig { reason: "ack_attempts must be at least 1" })? }
+		if !(0.0..=1.0).contains(&config.edge_decay) || !(0.0..=1.0).contains(&config.imported_edge_weight) {
+			Err(NodeError::InvalidConfig { reason: "edge weights must be between 0.0 and 1.0" })?
+		}
+		// Open sessions were set up with the old encryption, switching would make every one of them unreadable
+		if config.encryption != self.config.encryption && !self.sessions.is_empty() {
+			Err(NodeError::InvalidConfig { reason: "encryption can't change while sessions are open" })?
+		}
+
+		let old = std::mem::replace(&mut self.config, config);
+		if old.ping_window != self.config.ping_window {
+			for remote in self.remotes.values_mut() {
+				if let Some(session) = remote.session.as_mut() {
+					session.tracker.set_window(self.config.ping_window)?;
+				}
+			}
+		}
+		while self.recent_coords.len() > self.config.oscillation_window { self.recent_coords.pop_front(); }
+		self.trim_coordinate_history();
+		if old.target_peer_count != self.config.target_peer_count {
+			self.action(NodeAction::CalculatePeers);
+		}
+		Ok(())
+	}
+	/// Number of directly connected remotes that could be used by `calculate_route_coord`
+	fn usable_anchor_count(&self) -> usize {
+		let min_samples = self.config.min_peer_samples;
+		self.direct_sorted.values()
+			.filter_map(|&node_idx| self.remote(node_idx).ok())
+			.filter(|remote| remote.coord_trusted && remote.route_coord.is_some())
+			.filter(|remote| remote.session().map_or(false, |s| s.tracker.ping_count >= min_samples))
+			.count()
+	}
 	fn calculate_route_coord(&mut self) -> Result<RouteCoord, NodeError> {
 		// TODO: THIS CODE IS TERRIBLE AND NOT FUTURE-PROOF, NEEDS REIMPLEMENTATION FOR 3 DIMENSIONS AND FIX PRECISION ISSUES
 		struct NodeCircle {
 			coord: Vector2<f64>,
 			dist: f64,
+			weight: f64,
 			list_index: usize,
 		}
 
@@ -1071,17 +2996,25 @@ impl Node {
 		let closest_nodes = self.direct_sorted.iter().enumerate().filter_map(|(idx, (_,node_idx))| {
 			let result: anyhow::Result<NodeCircle> = try {
 				let node = self.remote(*node_idx)?;
+				if !node.coord_trusted { Err(NodeError::NoRemoteRouteCoord { remote: node.node_id })? }
+				let tracker = &node.session()?.tracker;
+				// Links with too few samples should not influence the coordinate at all
+				if tracker.ping_count < self.config.min_peer_samples { Err(NodeError::NoRemoteRouteCoord { remote: node.node_id })? }
 				NodeCircle {
-					coord: node.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?.map(|s|s as f64).coords,
-					dist: node.session()?.tracker.dist_avg as f64,
+					coord: node.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?.as_f64().coords,
+					dist: tracker.dist_avg as f64,
+					weight: tracker.confidence() * if node.is_anchor { self.config.anchor_weight } else { 1.0 },
 					list_index: idx,
 				}
 			};
 			result.ok()
 		}).take(10).collect::<Vec<NodeCircle>>();
+		// Circles only intersect in pairs
+		if closest_nodes.len() < 2 { Err(NodeError::NoCalculatedRouteCoord)? }
 
+		// Each point is weighted by the confidence of the links it was derived from
 		let points = closest_nodes.iter().tuple_combinations().filter_map(|(node_a, node_b)| {
-			let result: anyhow::Result<Vector2<f64>> = try {
+			let result: anyhow::Result<(Vector2<f64>, f64)> = try {
 				// Algorithm from: https://www.desmos.com/calculator/9mkzwevrns and https://math.stackexchange.com/questions/256100/how-can-i-find-the-points-at-which-two-circles-intersect
 				let dist = node_a.coord.metric_distance(&node_b.coord);
 				//let dist = nalgebra::distance(&node_a.coord, &circle_b.coord); // Distance
@@ -1102,16 +3035,20 @@ impl Node {
 					.map(|s|{
 						let dist_intersect_1 = (intersection_1 - s.coord).magnitude() - s.dist;
 						let dist_intersect_2 = (intersection_2 - s.coord).magnitude() - s.dist;
-						if dist_intersect_1 < dist_intersect_2 { intersection_1 } else { intersection_2 }
-					}).collect::<Vec<Vector2<f64>>>();
-				// Calculate Average
-				intersection_points.iter().fold(Vector2::new(0.0,0.0), |acc, &x| acc + x) / intersection_points.len() as f64
+						(if dist_intersect_1 < dist_intersect_2 { intersection_1 } else { intersection_2 }, s.weight)
+					}).collect::<Vec<(Vector2<f64>, f64)>>();
+				// Calculate Weighted Average
+				let total_weight = intersection_points.iter().map(|(_, w)| w).sum::<f64>();
+				if total_weight <= 0.0 { Err(NodeError::NoCalculatedRouteCoord)? }
+				let point = intersection_points.iter().fold(Vector2::new(0.0,0.0), |acc, &(x, w)| acc + x * w) / total_weight;
+				(point, node_a.weight * node_b.weight)
 			};
 			result.ok()
-		}).collect::<Vec<Vector2<f64>>>();
-		let average_point = points.iter().fold(Vector2::new(0.0,0.0), |acc, &x| acc + x) / points.len() as f64;
-		let average_point = average_point.map(|s|s as i64);
-		Ok(Point::from(average_point))
+		}).collect::<Vec<(Vector2<f64>, f64)>>();
+		let total_weight = points.iter().map(|(_, w)| w).sum::<f64>();
+		if total_weight <= 0.0 { Err(NodeError::NoCalculatedRouteCoord)? }
+		let average_point = points.iter().fold(Vector2::new(0.0,0.0), |acc, &(x, w)| acc + x * w) / total_weight;
+		Ok(RouteCoord::from_f64(Point::from(average_point)))
 	}
 }
 
@@ -0,0 +1,263 @@
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// How packets are encrypted on the wire.
+/// There is no real cryptography yet, Symmetric and PublicKey packets are only scrambled with a placeholder keystream (see `apply_keystream`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encryption {
+	/// Plaintext packets, useful for inspecting packet contents while debugging
+	None,
+	/// Packets are encrypted with the session's symmetric key
+	Symmetric,
+	/// Packets are encrypted with the recipient's public key
+	PublicKey,
+}
+impl Encryption {
+	/// Byte that prefixes every packet on the wire to identify how it was encrypted
+	pub fn tag(self) -> u8 {
+		match self {
+			Encryption::None => 0,
+			Encryption::Symmetric => 1,
+			Encryption::PublicKey => 2,
+		}
+	}
+	pub fn from_tag(tag: u8) -> Option<Self> {
+		match tag {
+			0 => Some(Encryption::None),
+			1 => Some(Encryption::Symmetric),
+			2 => Some(Encryption::PublicKey),
+			_ => None,
+		}
+	}
+	/// Placeholder for real encryption: XOR `data` with a keystream derived from `key` and this mode, applying it again restores `data`.
+	/// This keeps packet contents from being read off the wire by accident, it is not secure
+	pub fn apply_keystream(self, key: u64, data: &mut [u8]) {
+		if self == Encryption::None { return }
+		// SplitMix64
+		let mut state = key ^ (self.tag() as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		for chunk in data.chunks_mut(8) {
+			state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+			let mut z = state;
+			z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+			z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+			z ^= z >> 31;
+			for (byte, key_byte) in chunk.iter_mut().zip(z.to_le_bytes().iter()) { *byte ^= key_byte }
+		}
+	}
+}
+impl Default for Encryption {
+	fn default() -> Self { Encryption::None }
+}
+
+/// How the next hop is chosen among peers when routing towards a coordinate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PeerSelection {
+	/// Always pick the peer closest to the destination
+	Closest,
+	/// Pick randomly among the `k` closest peers, weighted by inverse distance, to spread load
+	WeightedRandom { k: usize },
+}
+impl Default for PeerSelection {
+	fn default() -> Self { PeerSelection::Closest }
+}
+
+/// How a node picks a route coordinate before it has enough anchors to calculate one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoordInit {
+	/// Don't pick a coordinate until one can be calculated
+	Deferred,
+	/// Start at the origin
+	Origin,
+	/// Start near the first remote that shares its coordinate, offset by the measured distance in a random direction
+	NearBootstrap,
+}
+impl Default for CoordInit {
+	fn default() -> Self { CoordInit::Deferred }
+}
+
+/// How one coordinate is chosen when DHT replicas disagree about a node's coordinate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoordConsensus {
+	/// Take the most recently written replica
+	Freshest,
+	/// Take the per-axis median of all replicas, resists a single stale or malicious replica
+	Median,
+}
+impl Default for CoordConsensus {
+	fn default() -> Self { CoordConsensus::Median }
+}
+
+/// Tunable parameters of a Node
+#[derive(Debug, Clone, Derivative, Serialize, Deserialize)]
+#[derivative(Default)]
+pub struct NodeConfig {
+	/// Weight given to a fresh distance measurement when blending it into an existing route_map edge (0.0 - 1.0)
+	#[derivative(Default(value = "0.3"))]
+	pub edge_decay: f64,
+	/// Number of ticks a route_map edge may go without being refreshed before it is pruned
+	#[derivative(Default(value = "20000"))]
+	pub edge_expiry: usize,
+	/// Number of ticks between periodic maintenance sweeps
+	#[derivative(Default(value = "500"))]
+	pub sweep_interval: usize,
+	/// Periodically share known coordinates with peers
+	#[derivative(Default(value = "true"))]
+	pub gossip_coords: bool,
+	/// Maximum number of coordinates shared in a single gossip packet
+	#[derivative(Default(value = "8"))]
+	pub gossip_sample_size: usize,
+	/// Number of ticks to wait for a reachability connect-back before marking the node unreachable
+	#[derivative(Default(value = "3000"))]
+	pub reachability_timeout: usize,
+	/// Peers losing a larger fraction of pings than this are demoted but keep their session
+	#[derivative(Default(value = "0.5"))]
+	pub max_peer_loss_rate: f64,
+	/// Number of ticks to wait for any seed node to connect when bootstrapping off of multiple seeds
+	#[derivative(Default(value = "5000"))]
+	pub bootstrap_timeout: usize,
+	/// Number of ticks after a session is closed during which automatic reconnects to that node are suppressed
+	#[derivative(Default(value = "2000"))]
+	pub tombstone_cooldown: usize,
+	/// Encryption used for packets sent by this node, nodes must agree on this to communicate
+	pub encryption: Encryption,
+	/// Ask the destination of Traversed Session packets to acknowledge delivery
+	pub traversal_ack: bool,
+	/// Maximum nesting depth of packets (e.g. ConnectionInit containing packets) before a packet is rejected
+	#[derivative(Default(value = "8"))]
+	pub max_packet_depth: usize,
+	/// Minimum number of ping samples a link needs before it can be a peer or influence this node's coordinate
+	#[derivative(Default(value = "1"))]
+	pub min_peer_samples: usize,
+	/// Number of ticks after which this node's coordinate is recalculated during a sweep, 0 to only recalculate when new anchors appear
+	pub recalc_interval: usize,
+	/// Wall-clock time represented by a single tick, used by real-time drivers to schedule ticks and interpret distances
+	#[derivative(Default(value = "Duration::from_millis(1)"))]
+	pub tick_duration: Duration,
+	/// Strategy used by `find_closest_peer` to pick the next hop
+	pub peer_selection: PeerSelection,
+	/// Number of ticks to wait for an Ack before retransmitting a Reliable packet
+	#[derivative(Default(value = "500"))]
+	pub ack_timeout: usize,
+	/// Number of times a Reliable packet is sent before giving up
+	#[derivative(Default(value = "5"))]
+	pub ack_attempts: usize,
+	/// Minimum number of ticks between writes of this node's coordinate to the DHT
+	#[derivative(Default(value = "1000"))]
+	pub dht_publish_interval: usize,
+	/// Minimum distance this node's coordinate must move from the published one before it is republished
+	#[derivative(Default(value = "5.0"))]
+	pub dht_publish_threshold: f64,
+	/// Coordinate this node assumes until it can calculate one
+	pub coord_init: CoordInit,
+	/// Number of recent coordinate recalculations checked for oscillation, 0 to disable detection
+	#[derivative(Default(value = "6"))]
+	pub oscillation_window: usize,
+	/// Number of ticks an oscillating coordinate is frozen before it may be recalculated again
+	#[derivative(Default(value = "5000"))]
+	pub oscillation_freeze: usize,
+	/// Number of ticks a ConnectRouted waits for the target's coordinate and then for the session before failing
+	#[derivative(Default(value = "5000"))]
+	pub routed_connect_timeout: usize,
+	/// Maximum number of nodes kept in the route_map, least important nodes are evicted during sweeps. 0 for no limit
+	#[derivative(Default(value = "1024"))]
+	pub max_route_map_nodes: usize,
+	/// Strategy used to choose among replicated DHT coordinates
+	pub coord_consensus: CoordConsensus,
+	/// Traverse packets are only relayed to destinations within this distance of this node's coordinate, None to relay anywhere
+	pub relay_radius: Option<f64>,
+	/// Weight of distances imported with `merge_route_map` relative to directly measured ones (0.0 - 1.0)
+	#[derivative(Default(value = "0.5"))]
+	pub imported_edge_weight: f64,
+	/// Stop at the first packet or action error in a tick and return it from `Node::try_tick`, instead of logging it and continuing
+	pub strict: bool,
+	/// Number of ticks a change in peer_list membership must hold before the remote is sent a PeerNotify or PeerDrop, 0 to notify immediately
+	pub peer_notify_debounce: usize,
+	/// Number of ticks to wait before reconnecting to remembered remotes after losing every session, doubled on each failed attempt. 0 to disable
+	#[derivative(Default(value = "1000"))]
+	pub reconnect_interval: usize,
+	/// Number of ticks to wait for a DHT read response before retrying
+	#[derivative(Default(value = "2000"))]
+	pub dht_timeout: usize,
+	/// Number of times a DHT read is sent before the lookup fails
+	#[derivative(Default(value = "3"))]
+	pub dht_attempts: usize,
+	/// Maximum number of incoming-only peers (remotes routing through this node that it doesn't route through), separate from the outgoing peer target
+	#[derivative(Default(value = "64"))]
+	pub max_incoming_peers: usize,
+	/// Number of ticks `Node::peer_churn_rate` is averaged over
+	#[derivative(Default(value = "10000"))]
+	pub churn_window: usize,
+	/// Send a NodeEncryption::Error back to remotes whose packets fail to be handled
+	#[derivative(Default(value = "true"))]
+	pub report_errors: bool,
+	/// Number of peers kept in the peer_list and used for routing
+	#[derivative(Default(value = "10"))]
+	pub target_peer_count: usize,
+	/// Number of ping samples averaged into each session's distance measurement
+	#[derivative(Default(value = "10"))]
+	pub ping_window: usize,
+	/// Number of ticks to wait for an Acknowledge before first retransmitting a direct Handshake, doubled (with jitter) on each retransmission. 0 to disable
+	#[derivative(Default(value = "500"))]
+	pub handshake_retransmit_interval: usize,
+	/// Number of ticks after a direct Handshake is first sent that it stops being retransmitted
+	#[derivative(Default(value = "5000"))]
+	pub handshake_timeout: usize,
+	/// Number of committed coordinates kept by `Node::coordinate_history`, 0 to disable
+	#[derivative(Default(value = "64"))]
+	pub coordinate_history_size: usize,
+	/// Multiplier applied to the weight of anchor remotes (see `Node::set_anchor`) when calculating this node's coordinate
+	#[derivative(Default(value = "4.0"))]
+	pub anchor_weight: f64,
+	/// Number of ticks a remote that sent PeerRefuse is left out of peer selection before it is tried again
+	#[derivative(Default(value = "5000"))]
+	pub peer_refuse_cooldown: usize,
+}
+impl NodeConfig {
+	pub fn ticks_to_duration(&self, ticks: usize) -> Duration {
+		self.tick_duration.saturating_mul(u32::try_from(ticks).unwrap_or(u32::MAX))
+	}
+	/// Number of whole ticks that fit in `duration`
+	pub fn duration_to_ticks(&self, duration: Duration) -> usize {
+		if self.tick_duration.is_zero() { return 0 }
+		(duration.as_nanos() / self.tick_duration.as_nanos()) as usize
+	}
+	/// Convert a distance measured in ticks (e.g. `dist_avg`) to milliseconds
+	pub fn ticks_to_ms(&self, ticks: f64) -> f64 {
+		ticks * self.tick_duration.as_secs_f64() * 1000.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tick_conversions_round_trip() {
+		for &tick_duration in &[Duration::from_millis(1), Duration::from_micros(250), Duration::from_millis(20)] {
+			let config = NodeConfig { tick_duration, ..Default::default() };
+			for &ticks in &[0, 1, 37, 5000] {
+				assert_eq!(config.duration_to_ticks(config.ticks_to_duration(ticks)), ticks);
+			}
+			assert!((config.ticks_to_ms(10.0) - tick_duration.as_secs_f64() * 10_000.0).abs() < 1e-9);
+		}
+		let config = NodeConfig { tick_duration: Duration::from_millis(20), ..Default::default() };
+		assert_eq!(config.ticks_to_duration(3), Duration::from_millis(60));
+		// Partial ticks are rounded down
+		assert_eq!(config.duration_to_ticks(Duration::from_millis(59)), 2);
+	}
+
+	#[test]
+	fn keystream_round_trips() {
+		let plaintext = b"plaintext packet".to_vec();
+		let mut data = plaintext.clone();
+		Encryption::None.apply_keystream(7, &mut data);
+		assert_eq!(data, plaintext);
+		for &encryption in &[Encryption::Symmetric, Encryption::PublicKey] {
+			encryption.apply_keystream(7, &mut data);
+			assert_ne!(data, plaintext);
+			encryption.apply_keystream(7, &mut data);
+			assert_eq!(data, plaintext);
+		}
+	}
+}
@@ -1,5 +1,9 @@
 
-use super::{InternetPacket, NetAddr, NodeError, NodeID, RouteCoord, SessionID, session::PingID};
+use super::types::ROUTE_COORD_DIMENSIONS;
+use super::{Encryption, InternetPacket, NetAddr, NodeError, NodeID, RouteCoord, SessionID, session::PingID};
+
+/// Key every node uses with `Encryption::apply_keystream` until there are real session and public keys
+const PLACEHOLDER_KEY: u64 = 0x6469_7468_6572;
 
 /// Data structure that represents a NodeEncryption traversing through the network 
 #[derive(Derivative, Serialize, Deserialize, Clone)]
@@ -12,13 +16,40 @@ pub struct TraversedPacket {
 	pub encryption: NodeEncryption,
 	/// Signed & Assymetrically encrypted return location
 	pub origin: Option<RouteCoord>,
+	/// If set, destination should send a NodeEncryption::TraversalAck back to origin containing this tick
+	#[serde(default)]
+	pub ack: Option<usize>,
 }
 impl TraversedPacket {
 	pub fn new(destination: RouteCoord, encryption: NodeEncryption, origin: Option<RouteCoord>) -> NodePacket {
-		NodePacket::Traverse(Box::new( TraversedPacket { destination, encryption, origin } ))
+		NodePacket::Traverse(Box::new( TraversedPacket { destination, encryption, origin, ack: None } ))
+	}
+	/// Create a traversed packet whose destination acknowledges delivery, `sent_at` is echoed back to measure end-to-end latency
+	pub fn with_ack(destination: RouteCoord, encryption: NodeEncryption, origin: RouteCoord, sent_at: usize) -> NodePacket {
+		NodePacket::Traverse(Box::new( TraversedPacket { destination, encryption, origin: Some(origin), ack: Some(sent_at) } ))
 	}
 }
 
+/// Probe routed greedily towards a coordinate and back again to measure the round trip latency of that path
+#[derive(Derivative, Serialize, Deserialize, Clone)]
+#[derivative(Debug)]
+pub struct PathProbe {
+	/// Random id the origin uses to match the returning probe with its request
+	pub probe_id: u64,
+	/// Coordinate being probed
+	#[derivative(Debug(format_with="std::fmt::Display::fmt"))]
+	pub destination: RouteCoord,
+	/// Coordinate of the node that sent the probe
+	#[derivative(Debug(format_with="std::fmt::Display::fmt"))]
+	pub origin: RouteCoord,
+	/// Tick the origin sent the probe
+	pub sent_at: usize,
+	/// Number of hops taken so far, in both directions
+	pub hops: usize,
+	/// Set once the probe has reached the destination and is heading back to the origin
+	pub returning: bool,
+}
+
 /// Packets that are sent between nodes in this protocol.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum NodePacket {
@@ -43,10 +74,24 @@ pub enum NodePacket {
 	/// * `RouteCoord`: My Route Coordinate
 	/// * `usize`: Number of peers I have
 	PeerNotify(usize, RouteCoord, usize, u64),
+	/// Tell remote that this session is being closed
+	Disconnect,
+	/// Notify another node that it is no longer considered a peer
+	PeerDrop,
+	/// End the peer relationship in both directions while keeping the session open for direct traffic
+	Bye,
+	/// Sent in response to a PeerNotify when this node has no room for more incoming peers, the remote should stop routing through it
+	PeerRefuse,
+	/// Tell remote that this node has moved to a new network address
+	AddressUpdate(NetAddr),
+	/// Tell remote that this node is an anchor whose coordinate is pinned and never recalculated, or with None that it no longer is one
+	Anchor(Option<RouteCoord>),
 	/// Propose routing coordinates if nobody has any nodes
 	ProposeRouteCoords(RouteCoord, RouteCoord), // First route coord = other node, second route coord = myself
 	/// Proposed route coords (original coordinates, orientation, bool), bool = true if acceptable
 	ProposeRouteCoordsResponse(RouteCoord, RouteCoord, bool), 
+	/// Withdraw a proposal the remote accepted but this node could no longer adopt, the remote drops the coordinates it adopted if it still has them (same order as ProposeRouteCoords)
+	RetractRouteCoords(RouteCoord, RouteCoord),
 
 	/// ### Self-Organization System
 	/// Request a certain number of another node's peers that are closest to this node to make themselves known
@@ -61,15 +106,71 @@ pub enum NodePacket {
 	/// * `u64`: Distance to that nodeTraversedPacket
 	AcceptWantPing(NodeID, u64),
 
+	/// Ask remote to send an unsolicited NodeEncryption::Notify containing the nonce back to the address this session is from
+	ReachabilityProbe(u64),
+	/// Ask a remote to restate its Route Coordinate so it can be checked against measured distance
+	CoordChallenge,
+	/// Response to CoordChallenge
+	/// * `Option<RouteCoord>`: Claimed Route Coordinate of the responding node
+	CoordResponse(Option<RouteCoord>),
+
+	/// Ask a remote this node already has a session with for its current Route Coordinate, instead of querying the DHT
+	RequestCoord,
+	/// Response to RequestCoord
+	/// * `Option<RouteCoord>`: Current Route Coordinate of the responding node
+	RequestCoordResponse(Option<RouteCoord>),
+
+	/// Ask a remote for the Route Coordinates it knows of a batch of nodes, at most `MAX_COORD_QUERY` are answered
+	CoordQuery(Vec<NodeID>),
+	/// Response to CoordQuery, None for nodes the responder doesn't know a coordinate of
+	CoordQueryResponse(Vec<(NodeID, Option<RouteCoord>)>),
+
+	/// Share known Route Coordinates without going through the DHT
+	/// * `Vec<(NodeID, RouteCoord, usize)>`: Known coordinates and how many ticks ago they were learned
+	RouteCoordGossip(Vec<(NodeID, RouteCoord, usize)>),
+
+	/// Ask a node this node has a session with to introduce it to a target node
+	/// * `NodeID`: Node to be introduced to
+	ConnectRequest(NodeID),
+	/// Sent by an intermediate node to a target on behalf of a ConnectRequest, target should connect to the requester
+	/// * `NodeID`: Requesting node
+	/// * `NetAddr`: Address of requesting node
+	Introduction(NodeID, NetAddr),
+
+	/// ### Reliable Delivery
+	/// Packet that the remote must acknowledge with an Ack containing the request id, retransmitted until acknowledged
+	Reliable(u64, Box<NodePacket>),
+	/// Acknowledges a Reliable packet
+	Ack(u64),
+
 	/// Packet Traversed
 	/// Represents a packet that is traversed through the network to it's destination using a RouteCoord
 	Traverse(Box<TraversedPacket>),
+	/// Measures the end-to-end latency of the path Traverse packets would take to a coordinate without establishing a session
+	PathProbe(Box<PathProbe>),
 
 	/* /// Request a session that is routed through node to another RouteCoordinate
 	RoutedSessionRequest(RouteCoord),
 	RoutedSessionAccept(), */
 
-	Data(Vec<u8>)
+	Data(Vec<u8>),
+	/// Application defined packet, dispatched to the handler registered for the category with `Node::register_handler`
+	/// * `u16`: Application packet category
+	/// * `Vec<u8>`: Application data
+	Application(u16, Vec<u8>),
+}
+
+/// Category of a protocol-level failure reported to a remote with NodeEncryption::Error
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+	/// Packet could not be decoded or violated the protocol
+	Malformed,
+	/// Packet referred to a session the receiver doesn't have or hasn't finished establishing
+	UnknownSession,
+	/// Receiver understood the packet but refused to handle it (e.g. relay policy)
+	Refused,
+	/// Receiver failed to handle the packet for some other reason
+	Internal,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -86,22 +187,80 @@ pub enum NodeEncryption {
 	// Asymmetrically Encrypted notification (Data and Sender are encrypted with recipient's public key)
 	Notify { recipient: NodeID, data: u64, sender: NodeID },
 	// Signed Route Request, treated as a Notify type but requests a return Routed Session from the remote
-	Request { recipient: NodeID, requester: NodeID }
+	Request { recipient: NodeID, requester: NodeID },
+	/// Sent back to the origin of a Traverse packet that requested acknowledgement
+	/// * `sent_at`: Tick the origin sent the acknowledged packet
+	TraversalAck { recipient: NodeID, acknowledger: NodeID, sent_at: usize },
+	/// Tells the sender of a packet that it was rejected, so failures can be diagnosed from the sending side
+	/// * `context`: Human readable description of what went wrong
+	Error { recipient: NodeID, sender: NodeID, code: ErrorCode, context: String },
 }
 
 
 
 impl NodeEncryption {
-	pub fn package(&self, dest_addr: NetAddr) -> InternetPacket {
+	/// Encryption layer this packet uses when the node is configured with `mode`.
+	/// Only Session packets have a session key, handshakes happen before it exists and the rest are sent outside of a session so they use public key encryption
+	pub fn required_encryption(&self, mode: Encryption) -> Encryption {
+		match (mode, self) {
+			(Encryption::None, _) => Encryption::None,
+			(_, NodeEncryption::Session { .. }) => Encryption::Symmetric,
+			_ => Encryption::PublicKey,
+		}
+	}
+	pub fn package(&self, dest_addr: NetAddr, encryption: Encryption) -> InternetPacket {
+		// TODO: actual cryptography, the keystream only stands in for it
+		let encryption = self.required_encryption(encryption);
+		let mut data = vec![encryption.tag(), ROUTE_COORD_DIMENSIONS];
+		// Symmetric packets name the session whose key decrypts them in the clear
+		let key = match self {
+			NodeEncryption::Session { session_id, .. } if encryption == Encryption::Symmetric => {
+				bincode::serialize_into(&mut data, session_id).expect("Failed to encode packet");
+				*session_id
+			}
+			_ => PLACEHOLDER_KEY,
+		};
+		let header_len = data.len();
+		bincode::serialize_into(&mut data, self).expect("Failed to encode packet");
+		encryption.apply_keystream(key, &mut data[header_len..]);
 		InternetPacket {
 			src_addr: 0, // This should get filled in automatically for all outgoing packets
-			data: bincode::serialize(self).expect("Failed to encode packet"),
+			data,
 			dest_addr,
 			request: None,
 		}
 	}
-	pub fn unpackage(packet: &InternetPacket) -> Result<Self, bincode::Error> {
-		bincode::deserialize(&packet.data)
+	pub fn unpackage(packet: &InternetPacket, encryption: Encryption) -> Result<Self, NodeError> {
+		let (&tag, data) = packet.data.split_first().ok_or(NodeError::UnknownEncryption { tag: None })?;
+		let received = Encryption::from_tag(tag).ok_or(NodeError::UnknownEncryption { tag: Some(tag) })?;
+		// Coordinates of a different dimension would silently misparse, so reject the packet before decoding
+		let (&dimensions, data) = data.split_first().ok_or(NodeError::CoordDimensionMismatch { expected: ROUTE_COORD_DIMENSIONS, received: None })?;
+		if dimensions != ROUTE_COORD_DIMENSIONS {
+			return Err(NodeError::CoordDimensionMismatch { expected: ROUTE_COORD_DIMENSIONS, received: Some(dimensions) });
+		}
+		// Reject encrypted packets sent to a plaintext node and the other way around before decrypting anything
+		if (encryption == Encryption::None) != (received == Encryption::None) {
+			return Err(NodeError::EncryptionMismatch { expected: encryption, received });
+		}
+		let (key, data) = match received {
+			Encryption::Symmetric => (bincode::deserialize::<SessionID>(data)?, &data[std::mem::size_of::<SessionID>()..]),
+			_ => (PLACEHOLDER_KEY, data),
+		};
+		let mut data = data.to_vec();
+		received.apply_keystream(key, &mut data);
+		let decoded: Self = bincode::deserialize(&data)?;
+		let expected = decoded.required_encryption(encryption);
+		if received != expected {
+			return Err(NodeError::EncryptionMismatch { expected, received });
+		}
+		Ok(decoded)
+	}
+	/// SessionID whose key is needed to decrypt a Symmetric packet, read without decrypting it
+	pub fn session_key_id(packet: &InternetPacket) -> Option<SessionID> {
+		match packet.data.get(..2) {
+			Some(&[tag, _]) if tag == Encryption::Symmetric.tag() => bincode::deserialize(&packet.data[2..]).ok(),
+			_ => None,
+		}
 	}
 	/* pub fn wrap_traverse(self, session_id: SessionID, route_coord: RouteCoord) -> NodeEncryption {
 		let packet = NodePacket::Traverse(route_coord, Box::new(self));
@@ -121,6 +280,8 @@ impl NodeEncryption {
 			Session { session_id, packet:_ } => node.sessions.contains_left(&session_id),
 			Notify { recipient, data:_, sender:_ } => node.node_id == recipient,
 			Request { recipient, requester:_ } => node.node_id == recipient,
+			TraversalAck { recipient, .. } => node.node_id == recipient,
+			Error { recipient, .. } => node.node_id == recipient,
 		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+//! Pluggable strategies for deciding which remotes become peers
+
+use super::{RemoteNode, RouteCoord};
+
+/// Decides whether a directly connected remote is a viable peer
+pub trait PeerPolicy: Send + Sync {
+	/// Returns the remote's Route Coordinate if it should be considered for the peer list
+	fn viable_peer(&self, remote: &RemoteNode, self_route_coord: RouteCoord) -> Option<RouteCoord>;
+}
+
+/// Accepts any trusted, directly connected remote that has a Route Coordinate
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultPeerPolicy;
+impl PeerPolicy for DefaultPeerPolicy {
+	fn viable_peer(&self, remote: &RemoteNode, self_route_coord: RouteCoord) -> Option<RouteCoord> {
+		remote.is_viable_peer(self_route_coord)
+	}
+}
+
+pub(super) fn default_peer_policy() -> Box<dyn PeerPolicy> { Box::new(DefaultPeerPolicy) }
@@ -1,7 +1,12 @@
-use super::{InternetPacket, Node, NodeError, NodeID, NodePacket, RemoteSession, RouteCoord, SessionError, SessionID, session::SessionType};
+use super::{InternetPacket, NetAddr, Node, NodeError, NodeID, NodePacket, RemoteSession, RouteCoord, RouteScalar, SessionError, SessionID, session::SessionType};
 
 use thiserror::Error;
 
+/// Maximum relative error between claimed coordinate distance and measured distance
+pub(super) const COORD_TOLERANCE: f64 = 0.5;
+/// Number of consecutive failed coordinate checks before a remote's coordinate is untrusted
+const MAX_COORD_FAILURES: usize = 3;
+
 #[derive(Error, Debug)]
 pub enum RemoteNodeError {
     #[error("There is no active session with the node: {node_id:?}")]
@@ -24,6 +29,17 @@ pub struct RemoteNode {
 	// Received Route Coordinate of the Remote Node
 	#[derivative(PartialEq="ignore", Hash="ignore")]
 	pub route_coord: Option<RouteCoord>,
+	// Tick at which route_coord was last set (None if never set or unknown)
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	pub coord_updated: Option<usize>,
+	// False if the remote's claimed Route Coordinate repeatedly disagreed with measured distance
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	pub coord_trusted: bool,
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	coord_failures: usize,
+	// True if the remote announced that its Route Coordinate is pinned with NodePacket::Anchor
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	pub is_anchor: bool,
 	// If handshake is pending: Some(pending_session_id, time_sent_handshake, packets_to_send)
 	#[derivative(PartialEq="ignore", Hash="ignore")]
 	#[serde(skip)]
@@ -31,16 +47,36 @@ pub struct RemoteNode {
 	// Contains Session details if session is connected
 	#[derivative(PartialEq="ignore", Hash="ignore")]
 	pub session: Option<RemoteSession>, // Session object, is None if no connection is active
+	// Address of the last direct session with this remote and the tick it was closed, used to reconnect after isolation
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	pub last_net_addr: Option<NetAddr>,
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	pub last_seen: Option<usize>,
+	// Distance this remote is keyed by in Node::direct_sorted, so its entry can be replaced without scanning
+	#[derivative(PartialEq="ignore", Hash="ignore")]
+	pub direct_dist: Option<RouteScalar>,
 }
 impl RemoteNode {
 	pub fn new(node_id: NodeID) -> Self {
 		Self {
 			node_id,
 			route_coord: None,
+			coord_updated: None,
+			coord_trusted: true,
+			coord_failures: 0,
+			is_anchor: false,
 			pending_session: None,
 			session: None,
+			last_net_addr: None,
+			last_seen: None,
+			direct_dist: None,
 		}
 	}
+	/// Record a newly received Route Coordinate and when it was received
+	pub fn set_route_coord(&mut self, route_coord: Option<RouteCoord>, current_time: usize) {
+		self.route_coord = route_coord;
+		self.coord_updated = route_coord.map(|_| current_time);
+	}
 	pub fn session_active(&self) -> bool {
 		self.session.is_some() && self.pending_session.is_none()
 	}
@@ -53,6 +89,7 @@ impl RemoteNode {
 	/// Check if a peer is viable or not
 	// TODO: Create condition that rejects nodes if there is another closer node located in a specific direction
 	pub fn is_viable_peer(&self, _self_route_coord: RouteCoord) -> Option<RouteCoord> {
+		if !self.coord_trusted { return None }
 		if let (Some(route_coord), Some(session)) = (self.route_coord, &self.session) {
 			//let avg_dist = session.tracker.dist_avg;
 			//let route_dist = nalgebra::distance(route_coord.map(|s|s as f64), self_route_coord.map(|s|s as f64));
@@ -61,6 +98,24 @@ impl RemoteNode {
 			} else { None }
 		} else { None }
 	}
+	/// Check that a Route Coordinate claimed by the remote is consistent with the measured distances from the remote to `anchors`,
+	/// given as (anchor coordinate, measured distance to remote). Returns false if the check failed, remote's coordinate is marked untrusted after repeated failures
+	pub fn verify_route_coord(&mut self, claimed_route_coord: RouteCoord, anchors: &[(RouteCoord, f64)]) -> bool {
+		if anchors.is_empty() { return true } // Nothing to check against yet
+		let consistent = anchors.iter().all(|(anchor, measured)| {
+			let predicted = super::types::route_dist(&claimed_route_coord, anchor);
+			(predicted - measured).abs() / measured.max(1.0) <= COORD_TOLERANCE
+		});
+		if consistent {
+			self.coord_failures = 0;
+		} else {
+			self.coord_failures += 1;
+			if self.coord_failures >= MAX_COORD_FAILURES {
+				self.coord_trusted = false;
+			}
+		}
+		consistent
+	}
 	
 	/// Generate NodeEncryption from NodePacket doing whatever needs to be done to route it through the network securely
 	pub fn gen_packet(&self, packet: NodePacket, node: &Node) -> Result<InternetPacket, NodeError> {
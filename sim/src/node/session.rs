@@ -2,9 +2,11 @@
 
 #![allow(non_upper_case_globals)]
 
+use super::types::{scalar_from_f64, UNREACHABLE};
 use super::{RouteScalar, SessionID, NodeID, NodePacket, Node, NodeError, NetAddr, RouteCoord, NodeEncryption, InternetPacket, TraversedPacket};
 
-use std::{cmp::Reverse, collections::HashMap, mem::{Discriminant, discriminant}};
+use std::{cmp::Reverse, collections::{HashMap, VecDeque}, mem::{Discriminant, discriminant}};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use ta::{indicators::{SimpleMovingAverage, StandardDeviation}, Next};
 use thiserror::Error;
@@ -13,8 +15,31 @@ use priority_queue::PriorityQueue;
 /// Number that uniquely identifies a ping request so that multiple Pings may be sent at the same time
 pub type PingID = u64;
 
-const MAX_PENDING_PINGS: usize = 25;
+/// Number of recently received request ids remembered per session to ignore retransmissions
+const MAX_SEEN_REQUESTS: usize = 32;
+/// Default maximum number of pings awaiting a response per session
+pub const DEFAULT_MAX_PENDING_PINGS: usize = 25;
 pub const NUM_NODE_PACKETS: usize = 10;
+/// Maximum number of (packet type, sender) pairs remembered per session for rate-limiting
+const MAX_PACKET_TIMES: usize = NUM_NODE_PACKETS * 8;
+/// Default number of standard deviations above the average at which a ping sample is rejected as an outlier
+pub const DEFAULT_OUTLIER_DEVIATIONS: f64 = 3.0;
+/// Number of ping samples averaged into a tracker's distance by default
+pub const DEFAULT_PING_WINDOW: usize = 10;
+/// Number of samples required before outlier rejection kicks in
+const OUTLIER_MIN_SAMPLES: usize = 5;
+
+/// Read-only copy of a SessionTracker's estimator state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackerSnapshot {
+	pub pending_pings: usize,
+	pub dist_avg: RouteScalar,
+	pub dist_dev: RouteScalar,
+	pub ping_count: usize,
+	pub outliers: usize,
+	pub pings_evicted: usize,
+	pub loss_rate: f64,
+}
 
 #[derive(Derivative, Serialize, Deserialize)]
 #[derivative(Debug)]
@@ -31,41 +56,116 @@ pub struct SessionTracker {
 	#[derivative(Debug="ignore")]
 	#[serde(skip)]
 	ping_dev: StandardDeviation,
+	/// Number of samples averaged by ping_avg and ping_dev, change with `set_window`
+	window: usize,
 	pub ping_count: usize,
+	/// Total number of pings generated
+	pub pings_sent: usize,
+	/// Ping samples rejected for being too far above the average
+	pub outliers: usize,
+	/// Samples more than this many standard deviations above dist_avg are rejected
+	pub outlier_deviations: f64,
+	/// Maximum number of pings awaiting a response, the oldest is evicted and counted as lost when exceeded
+	pub max_pending_pings: usize,
+	/// Pings evicted from the pending queue before being acknowledged
+	pub pings_evicted: usize,
 }
 impl SessionTracker {
-	fn new() -> Self {
+	pub fn new() -> Self {
 		Self {
-			ping_queue: PriorityQueue::with_capacity(MAX_PENDING_PINGS),
-			dist_avg: 0,
+			ping_queue: PriorityQueue::with_capacity(DEFAULT_MAX_PENDING_PINGS),
+			dist_avg: UNREACHABLE, // No measurement yet
 			dist_dev: 0,
-			ping_avg: SimpleMovingAverage::new(10).unwrap(),
-			ping_dev: ta::indicators::StandardDeviation::new(10).unwrap(),
+			ping_avg: SimpleMovingAverage::new(DEFAULT_PING_WINDOW).unwrap(),
+			ping_dev: ta::indicators::StandardDeviation::new(DEFAULT_PING_WINDOW).unwrap(),
+			window: DEFAULT_PING_WINDOW,
 			ping_count: 0,
+			pings_sent: 0,
+			outliers: 0,
+			outlier_deviations: DEFAULT_OUTLIER_DEVIATIONS,
+			max_pending_pings: DEFAULT_MAX_PENDING_PINGS,
+			pings_evicted: 0,
 		}
 	}
-	// Generate Ping Packet
-	pub fn gen_ping(&mut self, gen_time: usize) -> PingID {
-		let ping_id: PingID = rand::random();
+	// Generate Ping Packet, `ping_id` should come from the node's seeded rng so runs are reproducible
+	pub fn gen_ping(&mut self, ping_id: PingID, gen_time: usize) -> PingID {
+		self.pings_sent += 1;
 		self.ping_queue.push(ping_id, Reverse(gen_time));
-		// There shouldn't be more than 25 pings pending
-		if self.ping_queue.len() >= MAX_PENDING_PINGS {
+		// Evict oldest pending ping, it is treated as lost
+		while self.ping_queue.len() > self.max_pending_pings {
 			self.ping_queue.pop();
+			self.pings_evicted += 1;
 		}
 		ping_id
 	}
 	// Acknowledge Ping Response packet
 	pub fn acknowledge_ping(&mut self, ping_id: PingID, current_time: usize) -> Result<RouteScalar, SessionError> {
 		if let Some(( _, Reverse(time_sent) )) = self.ping_queue.remove(&ping_id) {
-			let round_trip_time = current_time - time_sent;
+			let round_trip_time = current_time.saturating_sub(time_sent);
 			let distance = round_trip_time as f64 / 2.0;
-			self.dist_avg = self.ping_avg.next(distance) as RouteScalar;
-			//self.dist_dev = self.ping_dev.next(distance) as RouteScalar;
+			// Reject latency spikes once there are enough samples for the deviation to be meaningful
+			if self.ping_count >= OUTLIER_MIN_SAMPLES {
+				let deviation = (self.dist_dev as f64).max(self.dist_avg as f64 / 10.0).max(1.0);
+				if distance > self.dist_avg as f64 + self.outlier_deviations * deviation {
+					self.outliers += 1;
+					return Ok(self.dist_avg);
+				}
+			}
+			self.dist_avg = scalar_from_f64(self.ping_avg.next(distance));
+			self.dist_dev = scalar_from_f64(self.ping_dev.next(distance));
 			self.ping_count += 1;
 			Ok(self.dist_avg)
 		} else { Err(SessionError::UnknownPingID { ping_id }) }
 	}
 	pub fn pending_pings(&self) -> usize { self.ping_queue.len() }
+	pub fn window(&self) -> usize { self.window }
+	/// Resize the moving average window, the current average is carried over as the new window's history so dist_avg doesn't jump
+	pub fn set_window(&mut self, window: usize) -> Result<(), SessionError> {
+		if window == self.window { return Ok(()) }
+		let (mut ping_avg, mut ping_dev) = match (SimpleMovingAverage::new(window), StandardDeviation::new(window)) {
+			(Ok(avg), Ok(dev)) => (avg, dev),
+			_ => return Err(SessionError::InvalidWindow { window }),
+		};
+		for _ in 0..usize::min(self.ping_count, window) {
+			ping_avg.next(self.dist_avg as f64);
+			ping_dev.next(self.dist_avg as f64);
+		}
+		self.ping_avg = ping_avg;
+		self.ping_dev = ping_dev;
+		self.window = window;
+		Ok(())
+	}
+	/// Copy of the internal estimator state, for inspecting it in tests and debugging
+	pub fn debug_snapshot(&self) -> TrackerSnapshot {
+		TrackerSnapshot {
+			pending_pings: self.pending_pings(),
+			dist_avg: self.dist_avg,
+			dist_dev: self.dist_dev,
+			ping_count: self.ping_count,
+			outliers: self.outliers,
+			pings_evicted: self.pings_evicted,
+			loss_rate: self.loss_rate(),
+		}
+	}
+	/// Take over another tracker's pending pings so their responses can still be acknowledged
+	pub fn absorb_pending(&mut self, other: SessionTracker) {
+		for (ping_id, time_sent) in other.ping_queue.into_iter() {
+			self.ping_queue.push(ping_id, time_sent);
+		}
+		self.pings_sent += other.pings_sent;
+	}
+	/// Fraction of sent pings (excluding ones still pending) that were never acknowledged, evicted pings count as lost
+	pub fn loss_rate(&self) -> f64 {
+		let resolved = self.pings_sent.saturating_sub(self.pending_pings());
+		if resolved == 0 { return 0.0 }
+		resolved.saturating_sub(self.ping_count + self.outliers) as f64 / resolved as f64
+	}
+	/// Confidence in distance measurement, grows with number of samples and shrinks with the square of jitter (like an inverse variance weight)
+	pub fn confidence(&self) -> f64 {
+		let samples = usize::min(self.ping_count, 10) as f64;
+		let jitter = self.dist_dev as f64 / (self.dist_avg as f64).max(1.0);
+		samples / (1.0 + (4.0 * jitter).powi(2))
+	}
 }
 
 bitflags! {
@@ -75,6 +175,8 @@ bitflags! {
 		const Outgoing 	= 0b00000001;
 		const Incoming 	= 0b00000010;
 		const Mutual	= 0b00000011;
+		/// Remote refused to accept this node as an incoming peer
+		const Refused	= 0b00000100;
 	}
 }
 /// Represents directly connected session over plain internet
@@ -84,20 +186,42 @@ pub struct DirectSession {
 	pub net_addr: NetAddr,
 	/// Some(bool) if peered, Some(true) if reciprocal peer
 	pub peer_status: PeerStatus,
+	/// Tick at which a PeerRefuse from remote expires
+	#[serde(skip)]
+	refused_until: usize,
 }
 impl DirectSession {
 	pub fn new(net_addr: NetAddr) -> SessionType {
 		SessionType::Direct(DirectSession {
 			net_addr,
 			peer_status: PeerStatus::None,
+			refused_until: 0,
 		})
 	}
 	pub fn record_peer_notify(&mut self, rank: usize) {
 		self.peer_status.set(PeerStatus::Incoming, rank != usize::MAX);
+		// A remote that wants to peer with this node has room again
+		if rank != usize::MAX { self.peer_status.remove(PeerStatus::Refused) }
+	}
+	pub fn record_peer_drop(&mut self) {
+		self.peer_status.remove(PeerStatus::Incoming);
 	}
 	pub fn set_peer(&mut self, toggle: bool) {
 		self.peer_status.set(PeerStatus::Outgoing, toggle);
 	}
+	/// Remote refused this node as a peer, it isn't asked again until `until`
+	pub fn record_peer_refused(&mut self, until: usize) {
+		self.peer_status.remove(PeerStatus::Outgoing);
+		self.peer_status.insert(PeerStatus::Refused);
+		self.refused_until = until;
+	}
+	pub fn expire_peer_refusal(&mut self, ticks: usize) {
+		if ticks >= self.refused_until { self.peer_status.remove(PeerStatus::Refused) }
+	}
+	/// Remote routes through this node but this node doesn't route through it
+	pub fn is_incoming_only(&self) -> bool {
+		self.peer_status.contains(PeerStatus::Incoming) && !self.peer_status.contains(PeerStatus::Outgoing)
+	}
 }
 /// Represents a session that traverses packets through the dither network to its destination
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -116,6 +240,14 @@ pub struct RoutedSession {
 	pub proxy_nodes: Vec<SessionID>,
 }
 
+/// Fieldless version of SessionType for cheap comparisons
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionKind {
+	Direct,
+	Traversed,
+	Routed,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum SessionType {
 	Direct(DirectSession),
@@ -126,6 +258,13 @@ impl SessionType {
 	pub fn direct(net_addr: NetAddr) -> Self { DirectSession::new(net_addr) }
 	pub fn traversed(route_coord: RouteCoord) -> Self { TraversedSession::new(route_coord) }
 	pub fn routed(route_coord: RouteCoord, proxy_nodes: Vec<SessionID>) -> Self { Self::Routed(RoutedSession { route_coord, proxy_nodes } ) }
+	pub fn kind(&self) -> SessionKind {
+		match self {
+			SessionType::Direct(_) => SessionKind::Direct,
+			SessionType::Traversed(_) => SessionKind::Traversed,
+			SessionType::Routed(_) => SessionKind::Routed,
+		}
+	}
 }
 
 #[derive(Error, Debug)]
@@ -138,6 +277,43 @@ pub enum SessionError {
 	InvalidCachedAddress,
 	#[error("No outgoing address")]
 	NoOutgoingAddress,
+	#[error("Moving average window must hold at least one sample, got {window}")]
+	InvalidWindow { window: usize },
+}
+
+/// Packet sent with NodePacket::Reliable that hasn't been acknowledged yet
+#[derive(Debug, Clone)]
+pub struct PendingAck {
+	pub packet: NodePacket,
+	/// Tick the packet was last (re)transmitted
+	pub sent_at: usize,
+	/// Number of times the packet has been sent
+	pub attempts: usize,
+}
+
+/// Cumulative traffic over a session, as seen by this node
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficStats {
+	pub packets_sent: u64,
+	pub bytes_sent: u64,
+	pub packets_received: u64,
+	pub bytes_received: u64,
+}
+impl std::ops::AddAssign for TrafficStats {
+	fn add_assign(&mut self, other: Self) {
+		self.packets_sent += other.packets_sent;
+		self.bytes_sent += other.bytes_sent;
+		self.packets_received += other.packets_received;
+		self.bytes_received += other.bytes_received;
+	}
+}
+/// Atomic counters so packets can be counted from `gen_packet`, which only borrows the session
+#[derive(Debug, Default)]
+struct TrafficCounters {
+	packets_sent: AtomicU64,
+	bytes_sent: AtomicU64,
+	packets_received: AtomicU64,
+	bytes_received: AtomicU64,
 }
 
 /// Represents a Remote Connection, Direct or Routed
@@ -155,6 +331,24 @@ pub struct RemoteSession {
 	#[derivative(Debug="ignore")]
 	#[serde(skip)]
 	pub last_packet_times: HashMap<(Discriminant<NodePacket>, NodeID), usize>, // Maps Packets to time last sent
+	/// Tick the handshake for this session was started or received
+	#[derivative(Debug="ignore")]
+	setup_started: Option<usize>,
+	/// Ticks between handshake and session establishment, set once when established
+	#[derivative(Debug="ignore")]
+	setup_latency: Option<usize>,
+	/// Reliable packets sent to remote awaiting a NodePacket::Ack, keyed by request id
+	#[derivative(Debug="ignore")]
+	#[serde(skip)]
+	pub pending_acks: HashMap<u64, PendingAck>,
+	/// Request ids recently received from remote, used to ignore retransmissions
+	#[derivative(Debug="ignore")]
+	#[serde(skip)]
+	seen_requests: VecDeque<u64>,
+	/// Packets and bytes exchanged with remote over this session
+	#[derivative(Debug="ignore")]
+	#[serde(skip)]
+	traffic: TrafficCounters,
 }
 impl RemoteSession {
 	pub fn new(session_id: SessionID, session_type: SessionType) -> Self {
@@ -163,8 +357,64 @@ impl RemoteSession {
 			session_type,
 			tracker: SessionTracker::new(),
 			last_packet_times: HashMap::with_capacity(NUM_NODE_PACKETS),
+			setup_started: None,
+			setup_latency: None,
+			pending_acks: HashMap::new(),
+			seen_requests: VecDeque::with_capacity(MAX_SEEN_REQUESTS),
+			traffic: TrafficCounters::default(),
 		}
 	}
+	/// Session with a fixed id for tests that assert on session ids
+	#[cfg(test)]
+	pub fn new_with_id(session_id: SessionID) -> Self {
+		Self::new(session_id, DirectSession::new(0))
+	}
+	/// Packets and bytes sent and received over this session so far
+	pub fn traffic_stats(&self) -> TrafficStats {
+		TrafficStats {
+			packets_sent: self.traffic.packets_sent.load(Ordering::Relaxed),
+			bytes_sent: self.traffic.bytes_sent.load(Ordering::Relaxed),
+			packets_received: self.traffic.packets_received.load(Ordering::Relaxed),
+			bytes_received: self.traffic.bytes_received.load(Ordering::Relaxed),
+		}
+	}
+	/// Count a packet of `bytes` length received from remote over this session
+	pub fn record_received(&self, bytes: usize) {
+		self.traffic.packets_received.fetch_add(1, Ordering::Relaxed);
+		self.traffic.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+	}
+	/// Record a received request id, returns false if it was already received (i.e. this is a retransmission)
+	pub fn record_request(&mut self, request_id: u64) -> bool {
+		if self.seen_requests.contains(&request_id) { return false }
+		if self.seen_requests.len() >= MAX_SEEN_REQUESTS { self.seen_requests.pop_front(); }
+		self.seen_requests.push_back(request_id);
+		true
+	}
+	/// Note when the handshake for this session began
+	pub fn start_setup(&mut self, current_time: usize) {
+		if self.setup_started.is_none() { self.setup_started = Some(current_time) }
+	}
+	/// Mark session as established, only the first call has an effect
+	pub fn finish_setup(&mut self, current_time: usize) {
+		if let (None, Some(started)) = (self.setup_latency, self.setup_started) {
+			self.setup_latency = Some(current_time.saturating_sub(started));
+		}
+	}
+	/// Number of ticks it took to establish this session, None if not yet established
+	pub fn setup_latency(&self) -> Option<usize> { self.setup_latency }
+	/// Carry over setup timing and traffic of a duplicate session to the same remote that is being replaced by this one
+	pub fn absorb_duplicate(&mut self, other: &RemoteSession) {
+		self.setup_started = match (self.setup_started, other.setup_started) {
+			(Some(a), Some(b)) => Some(a.min(b)),
+			(a, b) => a.or(b),
+		};
+		if self.setup_latency.is_none() { self.setup_latency = other.setup_latency }
+		let other_traffic = other.traffic_stats();
+		self.traffic.packets_sent.fetch_add(other_traffic.packets_sent, Ordering::Relaxed);
+		self.traffic.bytes_sent.fetch_add(other_traffic.bytes_sent, Ordering::Relaxed);
+		self.traffic.packets_received.fetch_add(other_traffic.packets_received, Ordering::Relaxed);
+		self.traffic.bytes_received.fetch_add(other_traffic.bytes_received, Ordering::Relaxed);
+	}
 	pub fn direct(&self) -> Result<&DirectSession, SessionError> {
 		if let SessionType::Direct(direct) = &self.session_type { Ok(direct) } else { Err(SessionError::NotDirectType) }
 	}
@@ -172,6 +422,8 @@ impl RemoteSession {
 		if let SessionType::Direct(direct) = &mut self.session_type { Ok(direct) } else { Err(SessionError::NotDirectType) }
 	}
 	pub fn is_peer(&self) -> bool { self.direct().map_or(false, |d|d.peer_status.contains(PeerStatus::Outgoing)) }
+	/// Both nodes consider each other peers, safe to route through in both directions
+	pub fn is_mutual_peer(&self) -> bool { self.direct().map_or(false, |d|d.peer_status.contains(PeerStatus::Mutual)) }
 	/// Returns how long ago (in ticks) a packet was last sent or None if packet has never been sent
 	pub fn check_packet_time(&mut self, packet: &NodePacket, sending_node_id: NodeID, current_time: usize) -> Option<usize> {
 		if let Some(last_time) = self.last_packet_times.get_mut(&(discriminant(packet), sending_node_id)) {
@@ -179,6 +431,12 @@ impl RemoteSession {
 			*last_time = current_time;
 			Some(difference)
 		} else { 
+			// Evict least recently received entry if map is full, only used for rate-limiting so it is safe to forget
+			if self.last_packet_times.len() >= MAX_PACKET_TIMES {
+				if let Some(oldest) = self.last_packet_times.iter().min_by_key(|(_, &time)| time).map(|(key, _)| *key) {
+					self.last_packet_times.remove(&oldest);
+				}
+			}
 			self.last_packet_times.insert((discriminant(packet), sending_node_id), current_time); None
 		}
 	}
@@ -193,21 +451,30 @@ impl RemoteSession {
 		let outgoing_net_addr = match &self.session_type {
 			SessionType::Direct(direct_session) => { direct_session.net_addr }
 			SessionType::Routed(routed_session) => {
+				// Replies travel straight back to this node, only the outgoing direction goes through the proxies
+				let self_route_coord = node.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
 				let mut current_route_coord = routed_session.route_coord;
+				// Each layer is wrapped with the session this node already has with that proxy, so only that proxy can unwrap it
 				for session_id in routed_session.proxy_nodes.iter().rev() {
-					// Handle these errors
-					let remote = node.remote(node.index_by_session_id(&session_id)?)?;
-					let origin_coord = remote.route_coord.unwrap();
+					let proxy = node.remote(node.index_by_session_id(&session_id)?)?;
+					let proxy_coord = proxy.route_coord.ok_or(NodeError::NoRemoteRouteCoord { remote: proxy.node_id })?;
 
-					let routed_packet = TraversedPacket::new(current_route_coord, encryption, Some(origin_coord));
-					encryption = self.wrap_session(routed_packet);
-					current_route_coord = origin_coord;
+					let routed_packet = TraversedPacket::new(current_route_coord, encryption, Some(self_route_coord));
+					encryption = proxy.session()?.wrap_session(routed_packet);
+					current_route_coord = proxy_coord;
 				}
 
-				let node_idx = 
-					if let Some(node_idx) = node.peer_list.get_by_right(&current_route_coord) { *node_idx }
-					else { node.find_closest_peer(&current_route_coord)? };
-				node.remote(node_idx)?.session()?.direct()?.net_addr
+				if let Some(first_proxy) = routed_session.proxy_nodes.first() {
+					// Deliver the onion over the first proxy's own session, whatever type it is
+					let packet = node.remote(node.index_by_session_id(first_proxy)?)?.session()?.gen_packet(encryption, node)?;
+					self.traffic.packets_sent.fetch_add(1, Ordering::Relaxed);
+					self.traffic.bytes_sent.fetch_add(packet.data.len() as u64, Ordering::Relaxed);
+					return Ok(packet);
+				}
+				// Without proxies the session is traversed directly to the remote
+				let closest_session = node.remote(node.find_closest_peer(&current_route_coord)?)?.session()?;
+				encryption = closest_session.wrap_session(TraversedPacket::new(current_route_coord, encryption, Some(self_route_coord)));
+				closest_session.direct()?.net_addr
 			}
 			SessionType::Traversed(traversed_session) => {
 				// Destination Route Coord
@@ -221,13 +488,87 @@ impl RemoteSession {
 
 				// Wrap with traversed packet
 				let self_route_coord = node.route_coord.ok_or(NodeError::NoCalculatedRouteCoord)?;
-				let traversed_packet = TraversedPacket::new(route_coord, encryption, Some(self_route_coord));
+				let traversed_packet = if node.config.traversal_ack {
+					TraversedPacket::with_ack(route_coord, encryption, self_route_coord, node.ticks)
+				} else {
+					TraversedPacket::new(route_coord, encryption, Some(self_route_coord))
+				};
 				encryption = closest_session.wrap_session(traversed_packet);
 
 				closest_session.direct()?.net_addr
 			}
 		};
 
-		Ok(encryption.package(outgoing_net_addr))
+		let packet = encryption.package(outgoing_net_addr, node.config.encryption);
+		self.traffic.packets_sent.fetch_add(1, Ordering::Relaxed);
+		self.traffic.bytes_sent.fetch_add(packet.data.len() as u64, Ordering::Relaxed);
+		Ok(packet)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn packet_times_stay_bounded() {
+		let mut session = RemoteSession::new_with_id(0);
+		for sender in 0..5000 {
+			session.check_packet_time(&NodePacket::Disconnect, sender, sender as usize);
+		}
+		assert!(session.last_packet_times.len() <= MAX_PACKET_TIMES);
+		// Recent senders are still remembered, only the oldest are evicted
+		assert_eq!(session.check_packet_time(&NodePacket::Disconnect, 4999, 5000), Some(1));
+		assert_eq!(session.check_packet_time(&NodePacket::Disconnect, 0, 5000), None);
+	}
+
+	#[test]
+	fn latency_spike_is_rejected() {
+		let mut tracker = SessionTracker::new();
+		let mut time = 0;
+		for ping_id in 0..10 {
+			tracker.gen_ping(ping_id, time);
+			time += 20;
+			tracker.acknowledge_ping(ping_id, time).unwrap();
+		}
+		let dist_avg = tracker.dist_avg;
+		tracker.gen_ping(10, time);
+		tracker.acknowledge_ping(10, time + 2000).unwrap();
+		assert_eq!(tracker.dist_avg, dist_avg);
+		assert_eq!(tracker.outliers, 1);
+	}
+
+	#[test]
+	fn evicted_pings_count_as_lost() {
+		let mut tracker = SessionTracker::new();
+		tracker.max_pending_pings = 3;
+		for ping_id in 0..5 {
+			tracker.gen_ping(ping_id, ping_id as usize);
+		}
+		assert_eq!(tracker.pending_pings(), 3);
+		assert_eq!(tracker.pings_evicted, 2);
+		assert_eq!(tracker.loss_rate(), 1.0);
+		// Oldest pings were the ones evicted
+		assert!(tracker.acknowledge_ping(0, 10).is_err());
+		assert!(tracker.acknowledge_ping(4, 10).is_ok());
+	}
+
+	#[test]
+	fn snapshot_reflects_pings() {
+		let mut tracker = SessionTracker::new();
+		assert_eq!(tracker.debug_snapshot().dist_avg, UNREACHABLE);
+		// Distances of 10 and 20 ticks, and one ping still in flight
+		tracker.gen_ping(0, 0);
+		tracker.acknowledge_ping(0, 20).unwrap();
+		tracker.gen_ping(1, 20);
+		tracker.acknowledge_ping(1, 60).unwrap();
+		tracker.gen_ping(2, 60);
+		let snapshot = tracker.debug_snapshot();
+		assert_eq!(snapshot.pending_pings, 1);
+		assert_eq!(snapshot.ping_count, 2);
+		assert_eq!(snapshot.dist_avg, 15);
+		assert_eq!(snapshot.dist_dev, 5);
+		assert_eq!(snapshot.outliers, 0);
+		assert_eq!(snapshot.pings_evicted, 0);
+	}
+}
@@ -0,0 +1,1769 @@
+use rand::{SeedableRng, rngs::SmallRng};
+
+use super::*;
+use crate::internet::NetSim;
+
+/// Network of seeded nodes at fixed positions so link latencies are known, a node's NodeID and NetAddr are its index in `positions`
+fn placed_net(positions: &[(f32, f32)], seed: u64) -> (NetSim<Node>, SmallRng) {
+	let mut rng = SmallRng::seed_from_u64(seed);
+	let mut internet = NetSim::new();
+	for (node_id, &(x, y)) in positions.iter().enumerate() {
+		let node = Node::new(node_id as NodeID, internet.lease()).with_seed(seed + node_id as u64);
+		let net_addr = node.net_addr;
+		internet.add_node(node, &mut rng);
+		internet.router.node_map.get_mut(&net_addr).unwrap().position = Vector2::new(x, y);
+	}
+	(internet, rng)
+}
+
+/// Network of `num_nodes` nodes 20 apart on a line where every node after the first bootstraps off of NodeID 0
+fn bootstrapped_net(num_nodes: u32, seed: u64, ticks: usize) -> NetSim<Node> {
+	let positions: Vec<(f32, f32)> = (0..num_nodes).map(|i| (i as f32 * 20.0, 0.0)).collect();
+	let (mut internet, mut rng) = placed_net(&positions, seed);
+	for net_addr in 1..num_nodes as NetAddr {
+		internet.node_mut(net_addr).unwrap().action(NodeAction::Bootstrap(0, 0));
+	}
+	internet.tick(ticks, &mut rng);
+	internet
+}
+
+/// Node 0 with an established direct session to each of `remotes`, a remote's NodeID is also its NetAddr and SessionID
+fn node_with_sessions(remotes: &[NodeID]) -> Node {
+	let mut node = Node::new(0, 0).with_seed(0);
+	for &node_id in remotes {
+		let node_idx = node.add_remote(node_id).unwrap().0;
+		let session = RemoteSession::new(node_id, SessionType::direct(node_id as NetAddr));
+		node.install_session(node_idx, session, &mut PacketVec::new()).unwrap();
+	}
+	node
+}
+
+/// Nodes with NodeID and NetAddr both equal to their index
+fn nodes(count: u32) -> Vec<Node> {
+	(0..count).map(|node_id| Node::new(node_id, node_id as NetAddr).with_seed(node_id as u64)).collect()
+}
+
+/// Establish a direct session between two nodes without a handshake
+fn link(nodes: &mut [Node], a: usize, b: usize, session_id: SessionID) {
+	for &(from, to) in &[(a, b), (b, a)] {
+		let (remote_id, remote_addr) = (nodes[to].node_id, nodes[to].net_addr);
+		let node = &mut nodes[from];
+		let node_idx = node.add_remote(remote_id).unwrap().0;
+		node.install_session(node_idx, RemoteSession::new(session_id, SessionType::direct(remote_addr)), &mut PacketVec::new()).unwrap();
+	}
+}
+
+/// Learn a remote's coordinate without a session
+fn learn_coord(node: &mut Node, node_id: NodeID, route_coord: RouteCoord) {
+	node.add_remote(node_id).unwrap().1.route_coord = Some(route_coord);
+}
+
+/// Nodes on a line 10 apart with coordinates to match, each linked to and peering with its neighbors
+fn chain(count: u32) -> Vec<Node> {
+	let mut nodes = nodes(count);
+	for node in nodes.iter_mut() {
+		node.route_coord = Some(RouteCoord::new(node.node_id as i64 * 10, 0));
+	}
+	for b in 1..count as usize {
+		link(&mut nodes, b - 1, b, 100 + b as SessionID);
+		for &(from, to) in &[(b - 1, b), (b, b - 1)] {
+			let (to_id, to_coord) = (nodes[to].node_id, nodes[to].route_coord.unwrap());
+			learn_coord(&mut nodes[from], to_id, to_coord);
+			let node_idx = nodes[from].index_by_node_id(&to_id).unwrap();
+			nodes[from].peer_list.insert(node_idx, to_coord);
+		}
+	}
+	nodes
+}
+
+/// Give `node` one latency sample to each remote so they are eligible as peers, remotes must already have a session
+fn measure(node: &mut Node, remote_ids: &[NodeID], rtt: usize) {
+	node.ticks = node.ticks.max(rtt);
+	for &node_id in remote_ids { node.apply_latency_hint(node_id, rtt).unwrap(); }
+}
+
+fn peer_status(node: &Node, node_id: NodeID) -> PeerStatus {
+	let remote = node.remote(node.index_by_node_id(&node_id).unwrap()).unwrap();
+	remote.session().unwrap().direct().unwrap().peer_status
+}
+
+/// Tick nodes in lockstep, every packet arrives at the node with its dest_addr one round after it was sent. Simulator requests are dropped
+fn run(nodes: &mut [Node], rounds: usize) {
+	deliver(nodes, Vec::new(), rounds);
+}
+
+/// Like `run`, starting with `in_flight` packets arriving in the first round
+fn deliver(nodes: &mut [Node], mut in_flight: Vec<InternetPacket>, rounds: usize) {
+	for _ in 0..rounds {
+		let mut sent = Vec::new();
+		for node in nodes.iter_mut() {
+			let net_addr = node.net_addr;
+			let incoming: PacketVec = in_flight.drain_filter(|packet| packet.dest_addr == net_addr).collect();
+			for mut packet in node.tick(incoming).into_iter().filter(|packet| packet.request.is_none()) {
+				packet.src_addr = net_addr;
+				sent.push(packet);
+			}
+		}
+		in_flight.extend(sent);
+	}
+}
+
+fn session_id_with(node: &Node, node_id: NodeID) -> Option<SessionID> {
+	let remote = node.remote(node.index_by_node_id(&node_id).ok()?).ok()?;
+	remote.session().ok().map(|session| session.session_id)
+}
+
+#[test]
+fn same_seed_reproduces_session_ids() {
+	let session_ids = |seed| {
+		let internet = bootstrapped_net(2, seed, 100);
+		(session_id_with(internet.node(0).unwrap(), 1), session_id_with(internet.node(1).unwrap(), 0))
+	};
+	let (acceptor, initiator) = session_ids(7);
+	assert!(initiator.is_some());
+	assert_eq!(acceptor, initiator);
+	assert_eq!(session_ids(7), (acceptor, initiator));
+	assert_ne!(session_ids(8).1, initiator);
+
+	assert_eq!(RemoteSession::new_with_id(42).session_id, 42);
+}
+
+#[test]
+fn session_kind_matches_session_type() {
+	let mut node = Node::new(0, 0);
+	let sessions = vec![
+		(1, SessionType::direct(1), SessionKind::Direct),
+		(2, SessionType::traversed(RouteCoord::new(5, 5)), SessionKind::Traversed),
+		(3, SessionType::routed(RouteCoord::new(9, 9), vec![1]), SessionKind::Routed),
+	];
+	for (node_id, session_type, kind) in sessions {
+		node.add_remote(node_id).unwrap().1.session = Some(RemoteSession::new(node_id, session_type));
+		assert_eq!(node.session_kind(&node_id), Some(kind));
+	}
+	node.add_remote(4).unwrap();
+	assert_eq!(node.session_kind(&4), None);
+}
+
+#[test]
+fn exchange_info_coalesces_update_remote() {
+	let mut node = node_with_sessions(&[1]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	let mut outgoing = PacketVec::new();
+	for remote_ping in 1..=3 {
+		node.parse_node_packet(node_idx, NodePacket::ExchangeInfo(Some(RouteCoord::new(10, 10)), 2, remote_ping), &mut outgoing, 0).unwrap();
+	}
+	let updates: Vec<&NodeAction> = node.pending_actions().iter().filter(|action| matches!(action, NodeAction::UpdateRemote(..))).collect();
+	assert_eq!(updates.len(), 1);
+	assert!(matches!(updates[0], NodeAction::UpdateRemote(1, _, 2, 3)));
+}
+
+// Nodes and their errors must be able to cross thread / async boundaries
+#[test]
+fn node_and_error_are_send_sync() {
+	fn assert_send_sync<T: Send + Sync + 'static>() {}
+	assert_send_sync::<NodeError>();
+	assert_send_sync::<Node>();
+}
+
+#[test]
+fn pending_actions_lists_conditional_notify() {
+	let mut node = node_with_sessions(&[1]);
+	let queued_notify = |node: &Node| node.pending_actions().iter().any(|action| matches!(action,
+		NodeAction::Condition(NodeActionCondition::RemoteRouteCoord(1), notify) if matches!(**notify, NodeAction::Notify(1, 7))
+	));
+	node.action(NodeAction::Notify(1, 7).gen_condition(NodeActionCondition::RemoteRouteCoord(1)));
+	assert!(queued_notify(&node));
+	node.tick(PacketVec::new());
+	assert!(queued_notify(&node));
+
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	node.remote_mut(node_idx).unwrap().set_route_coord(Some(RouteCoord::new(20, 0)), 0);
+	let outgoing = node.tick(PacketVec::new());
+	assert!(!queued_notify(&node));
+	assert!(!outgoing.is_empty());
+}
+
+#[test]
+fn normalize_coordinates_bounds_drift() {
+	let mut node = node_with_sessions(&[1, 2]);
+	let offsets = [(1, Vector2::new(300, 0)), (2, Vector2::new(0, 400))];
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	for &(node_id, offset) in &offsets {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.remote_mut(node_idx).unwrap().route_coord = Some(RouteCoord::new(0, 0) + offset);
+	}
+	let drift = Vector2::new(1 << 30, -(1 << 30));
+	for _ in 0..1000 {
+		// Every update moves the whole embedding, as unanchored coordinate calculation may
+		*node.route_coord.as_mut().unwrap() += drift;
+		for remote in node.remotes.values_mut() {
+			if let Some(route_coord) = &mut remote.route_coord { *route_coord += drift; }
+		}
+		node.normalize_coordinates();
+
+		let self_coord = node.route_coord.unwrap();
+		assert!(self_coord.x.abs() <= 2 * COORD_RECENTER_THRESHOLD && self_coord.y.abs() <= 2 * COORD_RECENTER_THRESHOLD);
+		for &(node_id, offset) in &offsets {
+			let remote_coord = node.remote(node.index_by_node_id(&node_id).unwrap()).unwrap().route_coord.unwrap();
+			assert_eq!(remote_coord - self_coord, offset);
+		}
+	}
+}
+
+#[test]
+fn inconsistent_coord_response_is_flagged() {
+	let mut node = node_with_sessions(&[1, 2]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	node.apply_latency_hint(1, 200).unwrap();
+	node.apply_latency_hint(2, 200).unwrap();
+	let mut outgoing = PacketVec::new();
+
+	// Remote 1 claims to be 1000 away while measured at 100
+	let liar_idx = node.index_by_node_id(&1).unwrap();
+	for _ in 0..5 {
+		node.parse_node_packet(liar_idx, NodePacket::CoordResponse(Some(RouteCoord::new(1000, 0))), &mut outgoing, 0).unwrap();
+	}
+	let liar = node.remote(liar_idx).unwrap();
+	assert!(!liar.coord_trusted);
+	assert_eq!(liar.route_coord, None);
+
+	let honest_idx = node.index_by_node_id(&2).unwrap();
+	node.parse_node_packet(honest_idx, NodePacket::CoordResponse(Some(RouteCoord::new(0, 100))), &mut outgoing, 0).unwrap();
+	let honest = node.remote(honest_idx).unwrap();
+	assert!(honest.coord_trusted);
+	assert_eq!(honest.route_coord, Some(RouteCoord::new(0, 100)));
+}
+
+/// Node with links to remotes at known coordinates, each measured by a series of round trip times
+fn node_with_links(links: &[(RouteCoord, &[usize])]) -> Node {
+	let remote_ids: Vec<NodeID> = (1..=links.len() as NodeID).collect();
+	let mut node = node_with_sessions(&remote_ids);
+	node.ticks = 10_000;
+	for (&node_id, &(route_coord, rtts)) in remote_ids.iter().zip(links) {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.remote_mut(node_idx).unwrap().route_coord = Some(route_coord);
+		for &rtt in rtts { node.apply_latency_hint(node_id, rtt).unwrap(); }
+	}
+	node
+}
+
+fn coord_from_links(links: &[(RouteCoord, &[usize])]) -> RouteCoord {
+	node_with_links(links).calculate_route_coord().unwrap()
+}
+
+#[test]
+fn noisy_link_is_downweighted() {
+	let clean: &[usize] = &[200; 10];
+	let anchors = [(RouteCoord::new(100, 0), clean), (RouteCoord::new(-100, 0), clean), (RouteCoord::new(0, 100), clean)];
+	let without = coord_from_links(&anchors);
+	// A fourth link that overestimates its distance, once measured consistently and once with heavy jitter around the same average
+	let consistent: &[usize] = &[320; 10];
+	let noisy: &[usize] = &[80, 560, 80, 560, 80, 560, 80, 560, 80, 560];
+	let with_link = |rtts| {
+		let mut links = anchors.to_vec();
+		links.push((RouteCoord::new(0, -100), rtts));
+		types::route_dist(&coord_from_links(&links), &without)
+	};
+	assert!(with_link(noisy) * 3.0 < with_link(consistent));
+}
+
+#[test]
+fn single_circle_gives_no_coordinate() {
+	let clean: &[usize] = &[200; 10];
+	let mut node = node_with_links(&[(RouteCoord::new(100, 0), clean)]);
+	assert!(matches!(node.calculate_route_coord(), Err(NodeError::NoCalculatedRouteCoord)));
+	// Recalculating keeps the current coordinate instead of failing
+	node.route_coord = Some(RouteCoord::new(90, 10));
+	node.parse_action(NodeAction::CalcRouteCoord, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+	assert_eq!(node.route_coord, Some(RouteCoord::new(90, 10)));
+}
+
+#[test]
+fn connect_to_establishes_session() {
+	let (mut internet, mut rng) = placed_net(&[(0.0, 0.0), (30.0, 0.0)], 0);
+	internet.node_mut(1).unwrap().connect_to(0, 0);
+	internet.tick(200, &mut rng);
+	assert!(internet.node(1).unwrap().is_connected(&0));
+	assert!(internet.node(0).unwrap().is_connected(&1));
+	assert_eq!(internet.node(1).unwrap().session_kind(&0), Some(SessionKind::Direct));
+}
+
+#[test]
+fn route_edges_track_latency_and_expire() {
+	let mut node = Node::new(0, 0);
+	node.record_edge(0, 1, 100);
+	node.record_edge(0, 2, 50);
+	// Sustained change in latency is picked up gradually
+	node.record_edge(0, 1, 200);
+	let blended = node.route_map.edge_weight(0, 1).unwrap().dist;
+	assert!(blended > 100 && blended < 200);
+	for _ in 0..30 { node.record_edge(0, 1, 200); }
+	assert!(node.route_map.edge_weight(0, 1).unwrap().dist >= 198);
+
+	// Only the edge that keeps being measured survives past expiry
+	node.ticks = node.config.edge_expiry + 1;
+	node.record_edge(0, 1, 200);
+	node.sweep();
+	assert!(node.route_map.contains_edge(0, 1));
+	assert!(!node.route_map.contains_edge(0, 2));
+}
+
+#[test]
+fn gossip_reaches_two_hops() {
+	let mut nodes = nodes(3);
+	// Chain 0 - 1 - 2, each node peers with its neighbors
+	link(&mut nodes, 0, 1, 10);
+	link(&mut nodes, 1, 2, 11);
+	for &(from, to) in &[(0, 1), (1, 0), (1, 2), (2, 1)] {
+		let node_idx = nodes[from].index_by_node_id(&to).unwrap();
+		nodes[from].peer_list.insert(node_idx, RouteCoord::new(to as i64 * 10, 0));
+	}
+	nodes[0].route_coord = Some(RouteCoord::new(7, 7));
+
+	nodes[0].action(NodeAction::GossipCoords);
+	run(&mut nodes, 2);
+	nodes[1].action(NodeAction::GossipCoords);
+	run(&mut nodes, 2);
+
+	let learned = nodes[2].remote(nodes[2].index_by_node_id(&0).unwrap()).unwrap();
+	assert_eq!(learned.route_coord, Some(RouteCoord::new(7, 7)));
+	assert!(learned.session.is_none());
+}
+
+#[test]
+fn malformed_packet_shows_in_diagnostics() {
+	let mut node = Node::new(0, 0);
+	let malformed = InternetPacket { src_addr: 1, dest_addr: 0, data: vec![0xff, 0xff, 0xff], request: None };
+	node.tick(std::iter::once(malformed).collect());
+	let diagnostics = node.last_tick_diagnostics();
+	assert_eq!(diagnostics.dropped_packets, 1);
+	assert_eq!(diagnostics.errored_packets, 0);
+
+	node.tick(PacketVec::new());
+	assert!(node.last_tick_diagnostics().is_clean());
+}
+
+#[test]
+fn trace_route_matches_forwarding() {
+	let mut nodes = chain(4);
+	for node_id in 2..4 {
+		learn_coord(&mut nodes[0], node_id, RouteCoord::new(node_id as i64 * 10, 0));
+	}
+	nodes[0].record_edge(1, 2, 10);
+	nodes[0].record_edge(2, 3, 10);
+	let traced = nodes[0].trace_route(RouteCoord::new(30, 0), 10);
+	assert_eq!(traced, TracedRoute { path: vec![0, 1, 2, 3], reached: true });
+
+	nodes[0].action(NodeAction::ConnectTraversed(3, vec![]));
+	run(&mut nodes, 10);
+	// Every relay received the packet from the node before it on the traced path
+	for hop in traced.path.windows(3) {
+		assert!(nodes[hop[1] as usize].relay_clients().contains_key(&hop[0]));
+	}
+	assert_eq!(nodes[0].session_kind(&3), Some(SessionKind::Traversed));
+}
+
+#[test]
+fn demoted_peer_clears_incoming() {
+	let mut nodes = chain(2);
+	nodes[0].peer_list.clear();
+	nodes[0].config.peer_notify_debounce = 0;
+	measure(&mut nodes[0], &[1], 20);
+	nodes[0].action(NodeAction::CalculatePeers);
+	run(&mut nodes, 2);
+	assert!(peer_status(&nodes[1], 0).contains(PeerStatus::Incoming));
+
+	// No room for peers anymore, so 1 is demoted
+	nodes[0].config.target_peer_count = 0;
+	nodes[0].action(NodeAction::CalculatePeers);
+	run(&mut nodes, 2);
+	assert!(!peer_status(&nodes[0], 1).contains(PeerStatus::Outgoing));
+	assert!(!peer_status(&nodes[1], 0).contains(PeerStatus::Incoming));
+}
+
+#[test]
+fn blocked_connect_back_marks_unreachable() {
+	let mut nodes = chain(3);
+	// Node 2 drops everything sent outside of a session, like a NAT would
+	nodes[2].set_drop_filter(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None), Ok(NodeEncryption::Notify { .. })));
+	for node in nodes.iter_mut() { node.config.reachability_timeout = 10 }
+	nodes[0].action(NodeAction::TestReachability(1));
+	nodes[2].action(NodeAction::TestReachability(1));
+	run(&mut nodes, 20);
+	assert_eq!(nodes[0].reachable, Some(true));
+	assert!(!nodes[2].is_reachable());
+}
+
+#[test]
+fn lossy_peer_is_demoted_not_expired() {
+	let mut nodes = chain(2);
+	nodes[0].config.max_peer_loss_rate = 0.25;
+	measure(&mut nodes[0], &[1], 20);
+	// Half of the resolved pings were never acknowledged
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	nodes[0].remote_mut(node_idx).unwrap().session_mut().unwrap().tracker.pings_sent += 1;
+	assert_eq!(nodes[0].remote(node_idx).unwrap().session().unwrap().tracker.loss_rate(), 0.5);
+
+	nodes[0].action(NodeAction::CalculatePeers);
+	run(&mut nodes, 1);
+	assert!(!nodes[0].peer_list.contains_left(&node_idx));
+	assert_eq!(session_id_with(&nodes[0], 1), Some(101));
+}
+
+#[test]
+fn introduction_connects_through_shared_peer() {
+	let mut nodes = chain(3);
+	assert_eq!(session_id_with(&nodes[0], 2), None);
+	nodes[0].action(NodeAction::RequestIntroduction(1, 2));
+	run(&mut nodes, 10);
+	let session_id = session_id_with(&nodes[0], 2);
+	assert!(session_id.is_some());
+	assert_eq!(session_id_with(&nodes[2], 0), session_id);
+}
+
+#[test]
+fn bootstraps_off_only_live_seed() {
+	let mut nodes = nodes(2);
+	// NodeIDs 5 and 6 don't exist so their handshakes go nowhere
+	let node = nodes.pop().unwrap().bootstrap_from(&[(5, 5), (6, 6), (0, 0)]);
+	nodes.push(node);
+	run(&mut nodes, 10);
+	assert!(nodes[1].is_connected(&0));
+	for dead_seed in &[5, 6] {
+		let remote = nodes[1].remote(nodes[1].index_by_node_id(dead_seed).unwrap()).unwrap();
+		assert!(remote.pending_session.is_none());
+	}
+}
+
+#[test]
+fn notify_is_received() {
+	let mut nodes = chain(2);
+	nodes[0].action(NodeAction::Notify(1, 42));
+	run(&mut nodes, 3);
+	let notifies: Vec<NodeEvent> = nodes[1].drain_events().into_iter().filter(|event| matches!(event, NodeEvent::Notify { .. })).collect();
+	assert_eq!(notifies, vec![NodeEvent::Notify { sender: 0, data: 42 }]);
+	assert!(nodes[1].events().is_empty());
+}
+
+#[test]
+fn closed_session_is_not_reintroduced() {
+	let mut nodes = chain(3);
+	link(&mut nodes, 0, 2, 99);
+	nodes[0].action(NodeAction::Disconnect(2));
+	run(&mut nodes, 3);
+	assert!(nodes[2].is_tombstoned(&0));
+	assert_eq!(session_id_with(&nodes[2], 0), None);
+
+	// Introductions are automatic reconnects, so they are suppressed during the cooldown
+	nodes[0].action(NodeAction::RequestIntroduction(1, 2));
+	run(&mut nodes, 10);
+	assert_eq!(session_id_with(&nodes[0], 2), None);
+
+	// Explicit connects bypass the tombstone
+	nodes[2].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	run(&mut nodes, 10);
+	assert!(session_id_with(&nodes[0], 2).is_some());
+}
+
+#[test]
+fn misplaced_peer_has_large_residual() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 1, RouteCoord::new(10, 0));
+	learn_coord(&mut node, 2, RouteCoord::new(0, 10));
+	// Claims to be far away but is as close as the others
+	learn_coord(&mut node, 3, RouteCoord::new(200, 0));
+	measure(&mut node, &[1, 2, 3], 20);
+	let mut residuals = node.peer_residuals();
+	residuals.sort_by_key(|&(node_id, _)| node_id);
+	assert_eq!(residuals.len(), 3);
+	assert!(residuals[..2].iter().all(|&(_, residual)| residual.abs() < 1.0));
+	assert!(residuals[2].1.abs() > 100.0);
+}
+
+#[test]
+fn retry_gives_up_after_attempts() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	node.action(NodeAction::Connect(5, SessionType::direct(5), vec![]).gen_retry(3, 10));
+	let mut session_ids = HashSet::new();
+	for _ in 0..200 {
+		for packet in node.tick(PacketVec::new()) {
+			if let Ok(NodeEncryption::Handshake { recipient: 5, session_id, .. }) = NodeEncryption::unpackage(&packet, Encryption::None) {
+				session_ids.insert(session_id);
+			}
+		}
+	}
+	// Every attempt handshakes with a new SessionID, retransmits reuse it
+	assert_eq!(session_ids.len(), 3);
+}
+
+#[test]
+fn merged_coords_allow_routing() {
+	let mut nodes = chain(4);
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	nodes[0].remote_mut(node_idx).unwrap().set_route_coord(Some(RouteCoord::new(10, 0)), 0);
+	nodes[0].merge_known_coords(&[(1, RouteCoord::new(500, 0)), (2, RouteCoord::new(20, 0)), (3, RouteCoord::new(30, 0))]).unwrap();
+	// Live coordinates win over imported ones
+	assert_eq!(nodes[0].remote(node_idx).unwrap().route_coord, Some(RouteCoord::new(10, 0)));
+
+	nodes[0].action(NodeAction::ConnectTraversed(3, vec![]));
+	run(&mut nodes, 10);
+	assert_eq!(nodes[0].session_kind(&3), Some(SessionKind::Traversed));
+}
+
+#[test]
+fn plaintext_sessions_are_inspectable() {
+	let mut nodes = nodes(2);
+	for node in nodes.iter_mut() { node.config.encryption = Encryption::None }
+	nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	run(&mut nodes, 5);
+	assert!(nodes[0].is_connected(&1) && nodes[1].is_connected(&0));
+
+	nodes[1].action(NodeAction::SendData(0, b"plaintext".to_vec()));
+	let packet = nodes[1].tick(PacketVec::new()).into_iter()
+		.find(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None), Ok(NodeEncryption::Session { packet: NodePacket::Data(_), .. })))
+		.unwrap();
+	assert_eq!(packet.data[0], Encryption::None.tag());
+	assert!(packet.data.windows(9).any(|window| window == b"plaintext"));
+}
+
+#[test]
+fn encrypted_sessions_hide_payload() {
+	let mut nodes = nodes(2);
+	for node in nodes.iter_mut() { node.config.encryption = Encryption::Symmetric }
+	nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	run(&mut nodes, 5);
+	assert!(nodes[0].is_connected(&1) && nodes[1].is_connected(&0));
+
+	nodes[1].action(NodeAction::SendData(0, b"plaintext".to_vec()));
+	let outgoing = nodes[1].tick(PacketVec::new());
+	let packet = outgoing.iter()
+		.find(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::Symmetric), Ok(NodeEncryption::Session { packet: NodePacket::Data(_), .. })))
+		.unwrap();
+	assert_eq!(packet.data[0], Encryption::Symmetric.tag());
+	assert!(outgoing.iter().all(|packet| !packet.data.windows(9).any(|window| window == b"plaintext")));
+}
+
+#[test]
+fn session_counts_by_type() {
+	let mut node = node_with_sessions(&[1]);
+	let session_types = [(2, SessionType::traversed(RouteCoord::new(20, 0))), (3, SessionType::routed(RouteCoord::new(30, 0), vec![1]))];
+	for (node_id, session_type) in session_types.iter().cloned() {
+		let node_idx = node.add_remote(node_id).unwrap().0;
+		node.install_session(node_idx, RemoteSession::new(node_id, session_type), &mut PacketVec::new()).unwrap();
+	}
+	let counts = node.session_counts();
+	assert_eq!(counts, SessionCounts { direct: 1, traversed: 1, routed: 1 });
+	assert_eq!(counts.total(), 3);
+	assert_eq!(node.stats().session_counts, counts);
+}
+
+#[test]
+fn traversal_ack_reaches_origin() {
+	let mut nodes = chain(4);
+	for node_id in 2..4 {
+		learn_coord(&mut nodes[0], node_id, RouteCoord::new(node_id as i64 * 10, 0));
+	}
+	nodes[0].config.traversal_ack = true;
+	nodes[0].action(NodeAction::ConnectTraversed(3, vec![]));
+	run(&mut nodes, 20);
+	assert!(nodes[0].events().iter().any(|event| matches!(event, NodeEvent::TraversalAck { acknowledger: 3, .. })));
+}
+
+/// Two linked nodes that have picked each other as peers through CalculatePeers
+fn peered_pair() -> Vec<Node> {
+	let mut nodes = chain(2);
+	for (node_idx, remote_id) in [(0, 1), (1, 0)].iter().cloned() {
+		let node = &mut nodes[node_idx];
+		node.peer_list.clear();
+		node.config.peer_notify_debounce = 0;
+		measure(node, &[remote_id], 20);
+		node.action(NodeAction::CalculatePeers);
+	}
+	run(&mut nodes, 3);
+	nodes
+}
+
+#[test]
+fn peers_become_mutual() {
+	let nodes = peered_pair();
+	assert!(nodes[0].is_mutual_peer(&1) && nodes[1].is_mutual_peer(&0));
+	assert_eq!(nodes[0].mutual_peers(), vec![1]);
+	assert_eq!(nodes[1].mutual_peers(), vec![0]);
+}
+
+#[test]
+fn deeply_nested_packet_is_rejected() {
+	let mut node = node_with_sessions(&[1]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	let depth = node.config.max_packet_depth as session::PingID + 5;
+	// Every level acknowledges its own ping so only the nesting limit can stop parsing
+	let tracker = &mut node.remote_mut(node_idx).unwrap().session_mut().unwrap().tracker;
+	for ping_id in 0..=depth { tracker.gen_ping(ping_id, 0); }
+	let packet = (0..depth).rev().fold(NodePacket::ConnectionInit(depth, vec![]), |inner, ping_id| NodePacket::ConnectionInit(ping_id, vec![inner]));
+	let result = node.parse_node_packet(node_idx, packet, &mut PacketVec::new(), 0);
+	let max = node.config.max_packet_depth;
+	assert!(matches!(result, Err(NodeError::PacketNestingTooDeep { depth: reached, max: m }) if reached == max + 1 && m == max));
+}
+
+#[test]
+fn shortest_path_on_weighted_topology() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	for &(from, to, dist) in &[(0, 1, 10), (1, 3, 10), (0, 2, 5), (2, 3, 30), (0, 3, 50), (4, 5, 1)] {
+		node.record_edge(from, to, dist);
+	}
+	assert_eq!(node.shortest_path(0, 3), Some((vec![0, 1, 3], 20)));
+	assert_eq!(node.shortest_path(0, 5), None);
+	assert_eq!(node.shortest_path(0, 6), None);
+}
+
+#[test]
+fn exchange_info_refreshes_coord_age() {
+	let mut node = node_with_sessions(&[1]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	let exchange_info = |node: &mut Node| {
+		node.parse_node_packet(node_idx, NodePacket::ExchangeInfo(Some(RouteCoord::new(10, 0)), 0, 10), &mut PacketVec::new(), 0).unwrap();
+		node.tick(PacketVec::new());
+	};
+	assert_eq!(node.coord_age(1), None);
+	node.ticks = 10;
+	exchange_info(&mut node);
+	assert_eq!(node.coord_age(1), Some(1));
+	node.ticks = 500;
+	assert_eq!(node.coord_age(1), Some(490));
+	exchange_info(&mut node);
+	assert_eq!(node.coord_age(1), Some(1));
+}
+
+#[test]
+fn duplicate_acknowledge_is_ignored() {
+	let mut nodes = nodes(2);
+	let sent_from = |packets: PacketVec, src_addr: NetAddr| -> PacketVec {
+		packets.into_iter().map(|mut packet| { packet.src_addr = src_addr; packet }).collect()
+	};
+	nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	let handshake = sent_from(nodes[1].tick(PacketVec::new()), 1);
+	let ack: PacketVec = sent_from(nodes[0].tick(handshake), 0).into_iter()
+		.filter(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None), Ok(NodeEncryption::Acknowledge { .. })))
+		.collect();
+	assert_eq!(ack.len(), 1);
+
+	nodes[1].tick(ack.clone());
+	assert!(nodes[1].last_tick_diagnostics().is_clean());
+	let session_id = session_id_with(&nodes[1], 0);
+	assert!(session_id.is_some());
+	nodes[1].tick(ack);
+	assert!(nodes[1].last_tick_diagnostics().is_clean());
+	assert_eq!(session_id_with(&nodes[1], 0), session_id);
+	assert_eq!(nodes[1].session_counts().total(), 1);
+}
+
+#[test]
+fn peers_follow_address_change() {
+	let mut nodes = chain(2);
+	let mut outgoing = PacketVec::new();
+	nodes[1].set_net_addr(50, &mut outgoing).unwrap();
+	let updates = outgoing.into_iter().map(|mut packet| { packet.src_addr = 50; packet }).collect();
+	nodes[0].tick(updates);
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	assert_eq!(nodes[0].remote(node_idx).unwrap().session().unwrap().direct().unwrap().net_addr, 50);
+
+	nodes[0].action(NodeAction::Notify(1, 7));
+	run(&mut nodes, 3);
+	assert!(nodes[1].events().contains(&NodeEvent::Notify { sender: 0, data: 7 }));
+}
+
+#[test]
+fn peer_needs_enough_samples() {
+	let mut nodes = chain(2);
+	let node = &mut nodes[0];
+	node.peer_list.clear();
+	node.config.min_peer_samples = 3;
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	measure(node, &[1], 20);
+	node.action(NodeAction::CalculatePeers);
+	node.tick(PacketVec::new());
+	assert!(!node.peer_list.contains_left(&node_idx));
+
+	measure(node, &[1], 20);
+	measure(node, &[1], 20);
+	node.action(NodeAction::CalculatePeers);
+	node.tick(PacketVec::new());
+	assert!(node.peer_list.contains_left(&node_idx));
+}
+
+#[test]
+fn handshake_gets_one_acknowledge() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	let mut handshake = NodeEncryption::Handshake { recipient: 0, session_id: 5, signer: 1 }.package(0, Encryption::None);
+	handshake.src_addr = 1;
+	let ticks = node.ticks;
+	let response = node.handle_packet(handshake).unwrap();
+	assert_eq!(response.len(), 1);
+	assert_eq!(response[0].dest_addr, 1);
+	assert!(matches!(NodeEncryption::unpackage(&response[0], Encryption::None), Ok(NodeEncryption::Acknowledge { session_id: 5, acknowledger: 0, .. })));
+	assert_eq!(node.ticks, ticks);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn corrupted_state_fails_invariants() {
+	let mut nodes = chain(2);
+	assert_eq!(nodes[0].check_invariants(), Ok(()));
+	// Peer and session maps still point at a remote whose session is gone
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	nodes[0].remote_mut(node_idx).unwrap().session = None;
+	assert!(nodes[0].check_invariants().is_err());
+}
+
+#[test]
+fn connected_coord_request_skips_dht() {
+	let mut nodes = chain(2);
+	nodes[1].route_coord = Some(RouteCoord::new(15, 0));
+	nodes[0].action(NodeAction::RequestRouteCoord(1));
+	let outgoing = nodes[0].tick(PacketVec::new());
+	assert!(outgoing.iter().all(|packet| packet.request.is_none()));
+	assert!(outgoing.iter().any(|packet| packet.dest_addr == 1));
+
+	let requests = outgoing.into_iter().map(|mut packet| { packet.src_addr = 0; packet }).collect();
+	let responses = nodes[1].tick(requests).into_iter().map(|mut packet| { packet.src_addr = 1; packet }).collect();
+	nodes[0].tick(responses);
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	assert_eq!(nodes[0].remote(node_idx).unwrap().route_coord, Some(RouteCoord::new(15, 0)));
+}
+
+#[test]
+fn third_anchor_triggers_recalculation() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 1, RouteCoord::new(10, 0));
+	learn_coord(&mut node, 2, RouteCoord::new(0, 10));
+	measure(&mut node, &[1, 2, 3], 20);
+	node.route_anchor_count = 2;
+	let recalculates = |node: &mut Node, node_id: NodeID, route_coord: RouteCoord| {
+		let mut out_actions = ActionVec::new();
+		node.parse_action(NodeAction::UpdateRemote(node_id, Some(route_coord), 0, 10), &mut PacketVec::new(), &mut out_actions).unwrap();
+		out_actions.iter().any(|action| matches!(action, NodeAction::CalcRouteCoord))
+	};
+	// Refreshing an existing anchor doesn't add a new one
+	assert!(!recalculates(&mut node, 1, RouteCoord::new(10, 0)));
+	assert!(recalculates(&mut node, 3, RouteCoord::new(-10, 0)));
+}
+
+#[test]
+fn ready_fires_once() {
+	let mut nodes = chain(2);
+	// Sweeps recalculate peers, which would rebuild peer_list from under the test
+	nodes[0].config.sweep_interval = 0;
+	let ready_count = |node: &Node| node.events().iter().filter(|event| matches!(event, NodeEvent::Ready)).count();
+	let peer_list = std::mem::take(&mut nodes[0].peer_list);
+	nodes[0].tick(PacketVec::new());
+	assert!(!nodes[0].is_ready());
+	assert_eq!(ready_count(&nodes[0]), 0);
+
+	nodes[0].peer_list = peer_list.clone();
+	nodes[0].tick(PacketVec::new());
+	assert!(nodes[0].is_ready());
+	assert_eq!(ready_count(&nodes[0]), 1);
+
+	// Losing and regaining peers doesn't fire it again
+	nodes[0].peer_list.clear();
+	assert!(!nodes[0].is_ready());
+	nodes[0].tick(PacketVec::new());
+	nodes[0].peer_list = peer_list;
+	nodes[0].tick(PacketVec::new());
+	assert_eq!(ready_count(&nodes[0]), 1);
+}
+
+#[test]
+fn session_packet_before_handshake_is_rejected() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	node.config.encryption = Encryption::Symmetric;
+	node.action(NodeAction::Connect(1, SessionType::direct(1), vec![]));
+	let handshake = node.tick(PacketVec::new()).into_iter().next().unwrap();
+	// Handshakes can't use a session key that doesn't exist yet
+	assert_eq!(handshake.data[0], Encryption::PublicKey.tag());
+
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	let session_id = node.remote(node_idx).unwrap().pending_session.as_ref().unwrap().0;
+	let mut early = NodeEncryption::Session { session_id, packet: NodePacket::Data(vec![]) }.package(0, Encryption::Symmetric);
+	early.src_addr = 1;
+	assert_eq!(early.data[0], Encryption::Symmetric.tag());
+	let result = node.handle_packet(early);
+	assert!(matches!(result, Err(NodeError::SessionKeyNotEstablished { session_id: rejected }) if rejected == session_id));
+
+	// Without encryption there is no key to wait for, the session is just unknown
+	node.config.encryption = Encryption::None;
+	let mut plain = NodeEncryption::Session { session_id, packet: NodePacket::Data(vec![]) }.package(0, Encryption::None);
+	plain.src_addr = 1;
+	assert!(matches!(node.handle_packet(plain), Err(NodeError::InvalidSessionID { .. })));
+}
+
+#[test]
+fn rejecting_policy_empties_peer_list() {
+	struct RejectAll;
+	impl PeerPolicy for RejectAll {
+		fn viable_peer(&self, _remote: &RemoteNode, _self_route_coord: RouteCoord) -> Option<RouteCoord> { None }
+	}
+	let mut node = chain(2).remove(0).with_peer_policy(RejectAll);
+	measure(&mut node, &[1], 20);
+	node.action(NodeAction::CalculatePeers);
+	node.tick(PacketVec::new());
+	assert!(node.peer_list.is_empty());
+}
+
+#[test]
+fn forwarding_registers_relay_clients() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	for &(node_id, x, y) in &[(1, -10, 0), (2, 0, -10), (3, 100, 0)] {
+		learn_coord(&mut node, node_id, RouteCoord::new(x, y));
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.peer_list.insert(node_idx, RouteCoord::new(x, y));
+	}
+	for &predecessor in &[1, 2, 1] {
+		let encryption = NodeEncryption::Notify { recipient: 3, data: 0, sender: predecessor };
+		let packet = TraversedPacket::new(RouteCoord::new(100, 0), encryption, None);
+		let node_idx = node.index_by_node_id(&predecessor).unwrap();
+		node.parse_node_packet(node_idx, packet, &mut PacketVec::new(), 0).unwrap();
+	}
+	let relay_clients = node.relay_clients();
+	assert_eq!(relay_clients.len(), 2);
+	assert_eq!(relay_clients[&1], 2);
+	assert_eq!(relay_clients[&2], 1);
+}
+
+#[test]
+fn forwards_without_calculated_peers() {
+	let mut node = node_with_sessions(&[1, 2]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 2, RouteCoord::new(100, 0));
+	measure(&mut node, &[1, 2], 20);
+	assert!(node.peer_list.is_empty());
+
+	let encryption = NodeEncryption::Notify { recipient: 5, data: 0, sender: 1 };
+	let packet = TraversedPacket::new(RouteCoord::new(100, 0), encryption, None);
+	let mut outgoing = PacketVec::new();
+	node.parse_node_packet(node.index_by_node_id(&1).unwrap(), packet, &mut outgoing, 0).unwrap();
+	assert_eq!(outgoing.len(), 1);
+	assert_eq!(outgoing[0].dest_addr, 2);
+}
+
+#[test]
+fn unmeasured_link_sorts_farthest() {
+	let mut node = node_with_sessions(&[1, 2]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	let unmeasured = node.remote(node_idx).unwrap().session().unwrap().tracker.dist_avg;
+	assert!(!unmeasured.is_known());
+	node.set_direct_dist(node_idx, 1, unmeasured);
+	measure(&mut node, &[2], 20);
+	let order: Vec<NodeID> = node.direct_sorted.keys().map(|&(_, node_id)| node_id).collect();
+	assert_eq!(order, vec![2, 1]);
+}
+
+#[test]
+fn buffered_ticks_drain_in_one_batch() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	node.action(NodeAction::Connect(1, SessionType::direct(1), vec![]));
+	node.tick_buffered(PacketVec::new());
+	node.action(NodeAction::Connect(2, SessionType::direct(2), vec![]));
+	node.tick_buffered(PacketVec::new());
+	let mut dest_addrs: Vec<NetAddr> = node.drain_outgoing().iter().map(|packet| packet.dest_addr).collect();
+	dest_addrs.sort_unstable();
+	assert_eq!(dest_addrs, vec![1, 2]);
+	assert!(node.drain_outgoing().is_empty());
+}
+
+#[test]
+fn buffered_packets_are_filtered_once() {
+	use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+	let filtered = Arc::new(AtomicUsize::new(0));
+	let mut node = Node::new(0, 0).with_seed(0);
+	let counter = filtered.clone();
+	node.set_drop_filter(move |packet| { if packet.dest_addr == 1 { counter.fetch_add(1, Ordering::Relaxed); } false });
+	node.action(NodeAction::Connect(1, SessionType::direct(1), vec![]));
+	node.tick_buffered(PacketVec::new());
+	// The Handshake waiting in the outbox is not filtered or sent again by later ticks
+	node.tick_buffered(PacketVec::new());
+	assert_eq!(node.drain_outgoing().len(), 1);
+	assert_eq!(filtered.load(Ordering::Relaxed), 1);
+}
+
+/// Ticks node 1 took to establish a session with node 0, optionally losing its first Handshake
+fn setup_latency(lose_first_handshake: bool) -> usize {
+	use std::sync::atomic::{AtomicBool, Ordering};
+	let mut nodes = nodes(2);
+	nodes[1].config.handshake_retransmit_interval = 10;
+	if lose_first_handshake {
+		let lost = AtomicBool::new(false);
+		nodes[1].set_drop_filter(move |packet| {
+			matches!(NodeEncryption::unpackage(packet, Encryption::None), Ok(NodeEncryption::Handshake { .. })) && !lost.swap(true, Ordering::Relaxed)
+		});
+	}
+	nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	run(&mut nodes, 50);
+	assert!(nodes[1].stats().avg_setup_latency.is_some());
+	let remote = nodes[1].remote(nodes[1].index_by_node_id(&0).unwrap()).unwrap();
+	remote.session().unwrap().setup_latency().unwrap()
+}
+
+#[test]
+fn lost_handshake_increases_setup_latency() {
+	let clean = setup_latency(false);
+	assert!(setup_latency(true) >= clean + 10);
+}
+
+#[test]
+fn bye_releases_peers_but_keeps_session() {
+	let mut nodes = peered_pair();
+	nodes[0].action(NodeAction::Bye(1));
+	run(&mut nodes, 3);
+	for (node_idx, remote_id) in [(0, 1), (1, 0)].iter().cloned() {
+		let node = &nodes[node_idx];
+		assert!(node.is_connected(&remote_id));
+		assert!(!node.peer_list.contains_left(&node.index_by_node_id(&remote_id).unwrap()));
+		assert_eq!(peer_status(node, remote_id) & (PeerStatus::Outgoing | PeerStatus::Incoming), PeerStatus::None);
+	}
+}
+
+#[test]
+fn crossing_handshakes_converge() {
+	let mut nodes = nodes(2);
+	nodes[0].action(NodeAction::Connect(1, SessionType::direct(1), vec![]));
+	nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	run(&mut nodes, 20);
+	let session_id = session_id_with(&nodes[0], 1);
+	assert!(session_id.is_some());
+	assert_eq!(session_id_with(&nodes[1], 0), session_id);
+	assert!(nodes.iter().all(|node| node.sessions.len() == 1));
+
+	// Whichever order the duplicates are installed in, the lower SessionID is kept
+	let mut node = node_with_sessions(&[1]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	for &session_id in &[0, 5] {
+		node.install_session(node_idx, RemoteSession::new(session_id, SessionType::direct(1)), &mut PacketVec::new()).unwrap();
+	}
+	assert_eq!(session_id_with(&node, 1), Some(0));
+	assert_eq!(node.sessions.len(), 1);
+}
+
+#[test]
+fn coordinate_extent_bounds_known_nodes() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	assert_eq!(node.coordinate_extent(), None);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 1, RouteCoord::new(-20, 5));
+	learn_coord(&mut node, 2, RouteCoord::new(30, -10));
+	learn_coord(&mut node, 3, RouteCoord::new(10, 40));
+	node.add_remote(4).unwrap();
+	assert_eq!(node.coordinate_extent(), Some((RouteCoord::new(-20, -10), RouteCoord::new(30, 40))));
+}
+
+/// Whether node 0 gains a session with node 2 after asking node 1 for pings, optionally with node 2 dropping every WantPing
+fn expands_through_want_ping(drop_want_ping: bool) -> bool {
+	let mut nodes = chain(3);
+	measure(&mut nodes[1], &[0, 2], 20);
+	if drop_want_ping {
+		nodes[2].set_drop_filter(|packet| matches!(
+			NodeEncryption::unpackage(packet, Encryption::None),
+			Ok(NodeEncryption::Session { packet: NodePacket::WantPing(..), .. })
+		));
+	}
+	let mut request_pings = PacketVec::new();
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	nodes[0].send_packet(node_idx, NodePacket::RequestPings(5, nodes[0].route_coord), &mut request_pings).unwrap();
+	deliver(&mut nodes, request_pings.into_iter().map(|mut packet| { packet.src_addr = 0; packet }).collect(), 10);
+	nodes[0].is_connected(&2)
+}
+
+#[test]
+fn dropped_want_pings_prevent_expansion() {
+	assert!(expands_through_want_ping(false));
+	assert!(!expands_through_want_ping(true));
+}
+
+#[test]
+fn prune_removes_disconnected_subcomponent() {
+	let mut node = node_with_sessions(&[1]);
+	for &(from, to) in &[(0, 1), (1, 2), (5, 6), (6, 5)] {
+		node.record_edge(from, to, 10);
+	}
+	for &node_id in &[2, 5, 6] {
+		learn_coord(&mut node, node_id, RouteCoord::new(node_id as i64, 0));
+	}
+	node.action(NodeAction::PruneRoutes);
+	node.tick(PacketVec::new());
+	let mut remaining: Vec<NodeID> = node.route_map.nodes().collect();
+	remaining.sort_unstable();
+	assert_eq!(remaining, vec![0, 1, 2]);
+	assert!(node.index_by_node_id(&2).is_ok());
+	assert!(node.index_by_node_id(&5).is_err() && node.index_by_node_id(&6).is_err());
+}
+
+#[test]
+fn latency_hints_allow_immediate_coordinate() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	for &(node_id, x, y) in &[(1, 10, 0), (2, 0, 10), (3, -10, 0)] {
+		learn_coord(&mut node, node_id, RouteCoord::new(x, y));
+	}
+	node.ticks = 100;
+	for &node_id in &[1, 2, 3] { node.apply_latency_hint(node_id, 20).unwrap(); }
+	assert_eq!(node.direct_sorted.len(), 3);
+	node.action(NodeAction::CalcRouteCoord);
+	node.tick(PacketVec::new());
+	assert!(node.route_coord.is_some());
+}
+
+#[test]
+fn weighted_random_selection_spreads_traffic() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	for &(node_id, x, y) in &[(1, 90, 5), (2, 90, -5), (3, 85, 0)] {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.peer_list.insert(node_idx, RouteCoord::new(x, y));
+	}
+	let destination = RouteCoord::new(100, 0);
+	let next_hops = |node: &Node| (0..200).map(|_| node.find_closest_peer(&destination).unwrap()).collect::<HashSet<NodeIdx>>();
+	assert_eq!(next_hops(&node).len(), 1);
+	node.config.peer_selection = PeerSelection::WeightedRandom { k: 3 };
+	assert_eq!(next_hops(&node).len(), 3);
+}
+
+#[test]
+fn reliable_address_update_survives_loss() {
+	let mut nodes = chain(2);
+	nodes[1].config.ack_timeout = 5;
+	// First transmission is lost
+	nodes[1].set_net_addr(50, &mut PacketVec::new()).unwrap();
+	let pending_acks = |node: &Node| node.remote(node.index_by_node_id(&0).unwrap()).unwrap().session().unwrap().pending_acks.len();
+	assert_eq!(pending_acks(&nodes[1]), 1);
+
+	run(&mut nodes, 30);
+	let node_idx = nodes[0].index_by_node_id(&1).unwrap();
+	assert_eq!(nodes[0].remote(node_idx).unwrap().session().unwrap().direct().unwrap().net_addr, 50);
+	assert_eq!(pending_acks(&nodes[1]), 0);
+}
+
+#[test]
+fn jittery_coordinate_is_published_once_per_interval() {
+	let mut node = chain(2).remove(0);
+	node.config.target_peer_count = 1;
+	measure(&mut node, &[1], 20);
+	let dht_writes = |node: &mut Node, route_coord: RouteCoord| {
+		node.route_coord = Some(route_coord);
+		let mut outgoing = PacketVec::new();
+		node.parse_action(NodeAction::CalculatePeers, &mut outgoing, &mut ActionVec::new()).unwrap();
+		outgoing.iter().filter(|packet| matches!(packet.request, Some(InternetRequest::RouteCoordDHTWrite(..)))).count()
+	};
+	let mut writes = 0;
+	for step in 0..50 {
+		node.ticks += 10;
+		writes += dht_writes(&mut node, RouteCoord::new(step % 2, 0));
+	}
+	assert_eq!(writes, 1);
+	// Moving far after the interval has passed publishes again
+	node.ticks += node.config.dht_publish_interval;
+	assert_eq!(dht_writes(&mut node, RouteCoord::new(100, 0)), 1);
+}
+
+#[test]
+fn pinned_peer_survives_eviction() {
+	let mut node = chain(2).remove(0);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	measure(&mut node, &[1], 20);
+	// No room for unpinned peers
+	node.config.target_peer_count = 0;
+	node.pin_peer(1);
+	node.tick(PacketVec::new());
+	assert!(node.is_pinned(&1));
+	assert!(node.peer_list.contains_left(&node_idx));
+
+	node.unpin_peer(1);
+	node.tick(PacketVec::new());
+	assert!(!node.peer_list.contains_left(&node_idx));
+}
+
+#[test]
+fn never_forwards_to_own_address() {
+	let mut node = node_with_sessions(&[1, 3]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	// Remote 2's session resolves to this node's own address
+	let node_idx = node.add_remote(2).unwrap().0;
+	node.install_session(node_idx, RemoteSession::new(2, SessionType::direct(node.net_addr)), &mut PacketVec::new()).unwrap();
+	for &(node_id, x) in &[(1, 50), (2, 100), (3, -50)] {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.peer_list.insert(node_idx, RouteCoord::new(x, 0));
+	}
+	let destination = RouteCoord::new(100, 0);
+	assert_eq!(node.find_closest_peer(&destination).unwrap(), node.index_by_node_id(&1).unwrap());
+
+	let encryption = NodeEncryption::Notify { recipient: 9, data: 0, sender: 3 };
+	let mut outgoing = PacketVec::new();
+	node.parse_node_packet(node.index_by_node_id(&3).unwrap(), TraversedPacket::new(destination, encryption, None), &mut outgoing, 0).unwrap();
+	assert!(outgoing.iter().all(|packet| packet.dest_addr != node.net_addr));
+	assert!(outgoing.iter().any(|packet| packet.dest_addr == 1));
+}
+
+#[test]
+fn denser_neighborhood_estimates_larger_network() {
+	let estimate = |spacing: i64| {
+		let mut node = Node::new(0, 0).with_seed(0);
+		node.route_coord = Some(RouteCoord::new(0, 0));
+		node.config.target_peer_count = 3;
+		// Same extent for both, only the neighborhood density differs
+		learn_coord(&mut node, 1, RouteCoord::new(-1000, -1000));
+		learn_coord(&mut node, 2, RouteCoord::new(1000, 1000));
+		for i in 0..5 {
+			learn_coord(&mut node, 10 + i as NodeID, RouteCoord::new(spacing * (i + 1), 0));
+		}
+		node.estimated_network_size()
+	};
+	assert!(estimate(10) > estimate(100));
+
+	// Too few coordinates falls back to counting known nodes
+	let mut node = Node::new(0, 0).with_seed(0);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 1, RouteCoord::new(10, 0));
+	assert_eq!(node.estimated_network_size(), 2);
+}
+
+#[test]
+fn registered_handler_receives_application_packet() {
+	use std::sync::{Arc, Mutex};
+	let mut nodes = chain(2);
+	let received = Arc::new(Mutex::new(Vec::new()));
+	for node in nodes.iter_mut() {
+		let received = received.clone();
+		let node_id = node.node_id;
+		node.register_handler(7, move |sender, data| {
+			received.lock().unwrap().push((node_id, sender, data.clone()));
+			if data == b"ping" { Some(b"pong".to_vec()) } else { None }
+		});
+	}
+	nodes[0].action(NodeAction::SendApplication(1, 7, b"ping".to_vec()));
+	run(&mut nodes, 3);
+	assert_eq!(*received.lock().unwrap(), vec![(1, 0, b"ping".to_vec()), (0, 1, b"pong".to_vec())]);
+}
+
+#[test]
+fn exchange_info_after_close_is_dropped() {
+	let mut node = node_with_sessions(&[1]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	node.close_session(node_idx).unwrap();
+	let mut outgoing = PacketVec::new();
+	let result = node.parse_node_packet(node_idx, NodePacket::ExchangeInfo(Some(RouteCoord::new(10, 0)), 0, 10), &mut outgoing, 0);
+	assert!(result.is_ok());
+	assert!(outgoing.is_empty());
+	assert!(node.pending_actions().is_empty());
+}
+
+#[test]
+fn near_bootstrap_starts_next_to_first_remote() {
+	let first_coord = |coord_init: CoordInit| {
+		let mut node = node_with_sessions(&[1]);
+		node.config.coord_init = coord_init;
+		measure(&mut node, &[1], 20);
+		node.parse_action(NodeAction::UpdateRemote(1, Some(RouteCoord::new(100, 100)), 0, 10), &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+		node.route_coord
+	};
+	assert_eq!(first_coord(CoordInit::Deferred), None);
+	let route_coord = first_coord(CoordInit::NearBootstrap).unwrap();
+	// Offset from the bootstrap node by about the measured distance
+	assert!((types::route_dist(&route_coord, &RouteCoord::new(100, 100)) - 10.0).abs() <= 1.0);
+}
+
+#[test]
+fn reset_coordinate_recomputes_from_neighbors() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	for &(node_id, x, y) in &[(1, 10, 0), (2, 0, 10), (3, -10, 0)] {
+		learn_coord(&mut node, node_id, RouteCoord::new(x, y));
+	}
+	measure(&mut node, &[1, 2, 3], 20);
+	let expected = node.calculate_route_coord().unwrap();
+	// Stuck far away from the neighborhood, and published there
+	node.route_coord = Some(RouteCoord::new(5000, 5000));
+	node.public_route = node.route_coord;
+
+	node.reset_coordinate();
+	let outgoing = node.tick(PacketVec::new());
+	assert!(outgoing.iter().any(|packet| matches!(packet.request, Some(InternetRequest::RouteCoordDHTDelete(0)))));
+	assert_eq!(node.route_coord, Some(expected));
+}
+
+#[test]
+fn oscillating_coordinate_is_frozen() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	measure(&mut node, &[1, 2, 3], 20);
+	// Neighbors alternately pull this node towards one of two clusters
+	let recalculate = |node: &mut Node, pull: i64| {
+		for &(node_id, x, y) in &[(1, 10, 0), (2, 0, 10), (3, -10, 0)] {
+			learn_coord(node, node_id, RouteCoord::new(x + pull, y));
+		}
+		node.parse_action(NodeAction::CalcRouteCoord, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+		node.route_coord.unwrap()
+	};
+	for round in 0..node.config.oscillation_window as i64 {
+		recalculate(&mut node, if round % 2 == 0 { 50 } else { -50 });
+	}
+	assert!(!node.is_coordinate_stable());
+	let frozen = node.route_coord.unwrap();
+	assert_eq!(recalculate(&mut node, 50), frozen);
+	assert_eq!(recalculate(&mut node, -50), frozen);
+}
+
+#[test]
+fn session_traffic_is_counted_both_ways() {
+	let mut nodes = chain(2);
+	let traffic = |node: &Node, node_id: NodeID| node.remote(node.index_by_node_id(&node_id).unwrap()).unwrap().session().unwrap().traffic_stats();
+	nodes[0].action(NodeAction::SendData(1, vec![0; 100]));
+	let sent: PacketVec = nodes[0].tick(PacketVec::new()).into_iter().map(|mut packet| { packet.src_addr = 0; packet }).collect();
+	let sent_bytes = sent.iter().map(|packet| packet.data.len() as u64).sum::<u64>();
+	nodes[1].tick(sent);
+
+	let (sender, receiver) = (traffic(&nodes[0], 1), traffic(&nodes[1], 0));
+	assert!(sender.packets_sent >= 1 && sender.bytes_sent > 100);
+	assert_eq!(sender.bytes_sent, sent_bytes);
+	assert_eq!((receiver.packets_received, receiver.bytes_received), (sender.packets_sent, sender.bytes_sent));
+	assert_eq!(nodes[1].traffic_summary().packets_received, receiver.packets_received);
+}
+
+#[test]
+fn routed_connect_without_coordinate_fails_after_timeout() {
+	let mut node = chain(2).remove(0);
+	node.config.routed_connect_timeout = 20;
+	node.action(NodeAction::ConnectRouted(9, 2));
+	let routed_connects = |node: &Node| node.events().iter().filter_map(|event| match event {
+		NodeEvent::RoutedConnect { target, result } => Some((*target, *result)),
+		_ => None,
+	}).collect::<Vec<_>>();
+	// DHT requests are never answered
+	for _ in 0..10 { node.tick(PacketVec::new()); }
+	assert!(routed_connects(&node).is_empty());
+	for _ in 0..20 { node.tick(PacketVec::new()); }
+	assert_eq!(routed_connects(&node), vec![(9, Err(RoutedConnectFailure::NoRouteCoord))]);
+}
+
+#[test]
+fn capped_route_map_keeps_peers_and_anchors() {
+	let mut node = node_with_sessions(&[1, 2, 3, 4]);
+	node.config.max_route_map_nodes = 20;
+	for node_id in 1..=4 {
+		node.record_edge(0, node_id, 10);
+		// Long tail behind every direct remote
+		let mut prev = node_id;
+		for hop in 0..50 {
+			let next = node_id * 1000 + hop;
+			node.record_edge(prev, next, 10);
+			prev = next;
+		}
+	}
+	for &node_id in &[1, 2] {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.peer_list.insert(node_idx, RouteCoord::new(node_id as i64, 0));
+	}
+	let node_idx = node.index_by_node_id(&3).unwrap();
+	node.remote_mut(node_idx).unwrap().is_anchor = true;
+
+	node.cap_route_map();
+	assert!(node.route_map.node_count() <= 20);
+	for node_id in 0..=3 { assert!(node.route_map.contains_node(node_id)); }
+	// Nodes are evicted from the far end so whatever is kept is still reachable
+	let mut dfs = petgraph::visit::Dfs::new(&node.route_map, 0);
+	let mut reachable = 0;
+	while dfs.next(&node.route_map).is_some() { reachable += 1; }
+	assert_eq!(reachable, node.route_map.node_count());
+}
+
+#[test]
+fn fast_clock_still_measures_sane_distances() {
+	let (mut internet, mut rng) = placed_net(&[(0.0, 0.0), (20.0, 0.0)], 0);
+	internet.set_clock_skew(1, 1000, 1.1).unwrap();
+	internet.node_mut(1).unwrap().action(NodeAction::Bootstrap(0, 0));
+	internet.tick(1000, &mut rng);
+	let measured = |internet: &NetSim<Node>, net_addr: NetAddr, node_id: NodeID| {
+		let node = internet.node(net_addr).unwrap();
+		node.remote(node.index_by_node_id(&node_id).unwrap()).unwrap().session().unwrap().dist()
+	};
+	// One way latency is about 20, measured in each node's own ticks
+	for &(net_addr, node_id) in &[(0, 1), (1, 0)] {
+		let dist = measured(&internet, net_addr, node_id);
+		assert!(dist.is_known() && dist > 10 && dist < 40, "NodeID({}) measured {} to NodeID({})", net_addr, dist, node_id);
+	}
+}
+
+#[test]
+fn median_consensus_ignores_outlier_replica() {
+	let mut node = Node::new(0, 0).with_seed(0);
+	// The outlier is also the freshest replica
+	let replicas = [(RouteCoord::new(10, 20), 50), (RouteCoord::new(12, 18), 40), (RouteCoord::new(11, 21), 60), (RouteCoord::new(9000, -9000), 1)];
+	node.config.coord_consensus = CoordConsensus::Median;
+	let median = node.route_coord_consensus(&replicas).unwrap();
+	assert!(types::route_dist(&median, &RouteCoord::new(11, 20)) <= 2.0);
+	node.config.coord_consensus = CoordConsensus::Freshest;
+	assert_eq!(node.route_coord_consensus(&replicas), Some(RouteCoord::new(9000, -9000)));
+	assert_eq!(node.route_coord_consensus(&[]), None);
+}
+
+#[test]
+fn path_probe_measures_three_hop_latency() {
+	let mut nodes = chain(4);
+	for node in nodes.iter_mut() { node.config.sweep_interval = 0 }
+	nodes[0].action(NodeAction::ProbePath(RouteCoord::new(30, 0)));
+	run(&mut nodes, 10);
+	// Every hop takes one round in lockstep, so the round trip over 3 hops is 6 ticks
+	let probes: Vec<_> = nodes[0].events().iter().filter_map(|event| match event {
+		NodeEvent::PathProbe { destination, rtt, hops } => Some((*destination, *rtt, *hops)),
+		_ => None,
+	}).collect();
+	assert_eq!(probes, vec![(RouteCoord::new(30, 0), 6, 6)]);
+	assert!(nodes[0].pending_probes.is_empty());
+}
+
+#[test]
+fn mismatched_acknowledge_creates_no_session() {
+	let mut nodes = nodes(2);
+	nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+	let handshake = nodes[1].tick(PacketVec::new()).into_iter().map(|mut packet| { packet.src_addr = 1; packet }).collect();
+	let (session_id, return_ping_id) = nodes[0].tick(handshake).iter()
+		.find_map(|packet| match NodeEncryption::unpackage(packet, Encryption::None) {
+			Ok(NodeEncryption::Acknowledge { session_id, return_ping_id, .. }) => Some((session_id, return_ping_id)),
+			_ => None,
+		}).unwrap();
+	let ack = |session_id| {
+		let mut packet = NodeEncryption::Acknowledge { session_id, acknowledger: 0, return_ping_id }.package(1, Encryption::None);
+		packet.src_addr = 0;
+		packet
+	};
+
+	let _ = nodes[1].handle_packet(ack(session_id.wrapping_add(1)));
+	assert_eq!(session_id_with(&nodes[1], 0), None);
+	// The pending handshake survives, so the genuine ack still completes it
+	let _ = nodes[1].handle_packet(ack(session_id));
+	assert_eq!(session_id_with(&nodes[1], 0), Some(session_id));
+}
+
+#[test]
+fn relay_radius_refuses_far_destinations() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 2, RouteCoord::new(40, 0));
+	learn_coord(&mut node, 3, RouteCoord::new(1000, 0));
+	measure(&mut node, &[1, 2, 3], 20);
+	node.config.relay_radius = Some(100.0);
+	let from_idx = node.index_by_node_id(&1).unwrap();
+	let traverse = |x| TraversedPacket::new(RouteCoord::new(x, 0), NodeEncryption::Notify { recipient: 5, data: 0, sender: 1 }, None);
+
+	let mut outgoing = PacketVec::new();
+	node.parse_node_packet(from_idx, traverse(50), &mut outgoing, 0).unwrap();
+	assert_eq!(outgoing.len(), 1);
+	assert_eq!(outgoing[0].dest_addr, 2);
+
+	let mut outgoing = PacketVec::new();
+	let err = node.parse_node_packet(from_idx, traverse(1000), &mut outgoing, 0).unwrap_err();
+	assert!(matches!(err, NodeError::OutsideRelayRadius { .. }));
+	assert!(outgoing.is_empty());
+}
+
+#[test]
+fn merged_route_map_extends_topology() {
+	let mut node = node_with_sessions(&[1]);
+	node.record_edge(0, 1, 10);
+	// A peer's view of the network beyond it, including its own estimate of our link
+	node.merge_route_map(&[(0, 1, 500), (1, 2, 30), (2, 3, 40)]);
+	assert_eq!(node.route_map.edge_weight(0, 1).unwrap().dist, 10);
+	assert_eq!(node.shortest_path(0, 3), Some((vec![0, 1, 2, 3], 80)));
+
+	// Secondhand distances move an edge less than measured ones
+	let mut measured = node_with_sessions(&[1]);
+	measured.route_map = node.route_map.clone();
+	measured.record_edge(1, 2, 130);
+	node.merge_route_map(&[(1, 2, 130)]);
+	let (merged, measured) = (node.route_map.edge_weight(1, 2).unwrap().dist, measured.route_map.edge_weight(1, 2).unwrap().dist);
+	assert!(30 < merged && merged < measured);
+}
+
+#[test]
+fn strict_mode_returns_malformed_packet_error() {
+	let malformed = || std::iter::once(InternetPacket { src_addr: 1, dest_addr: 0, data: vec![0xff, 0xff, 0xff], request: None }).collect();
+	let mut node = Node::new(0, 0);
+	assert!(node.try_tick(malformed()).is_ok());
+
+	node.config.strict = true;
+	assert!(matches!(node.try_tick(malformed()), Err(NodeError::UnknownEncryption { tag: Some(0xff) })));
+	assert!(node.try_tick(PacketVec::new()).is_ok());
+}
+
+#[test]
+fn flapping_peer_is_notified_once() {
+	let mut node = node_with_sessions(&[1]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	learn_coord(&mut node, 1, RouteCoord::new(10, 0));
+	measure(&mut node, &[1], 20);
+	node.config.peer_notify_debounce = 10;
+	let mut notifies = 0;
+	let mut recompute = |node: &mut Node, released: bool| {
+		if released { node.released_peers.insert(1); } else { node.released_peers.remove(&1); }
+		let mut outgoing = PacketVec::new();
+		node.parse_action(NodeAction::CalculatePeers, &mut outgoing, &mut ActionVec::new()).unwrap();
+		notifies += outgoing.iter().filter(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None),
+			Ok(NodeEncryption::Session { packet: NodePacket::PeerNotify(..), .. }))).count();
+		node.ticks += 1;
+	};
+	for &released in &[false, true, false] { recompute(&mut node, released) }
+	node.ticks += 10;
+	recompute(&mut node, false);
+	assert_eq!(notifies, 1);
+}
+
+#[test]
+fn isolated_node_reconnects_from_memory() {
+	let mut nodes = chain(2);
+	for (node_idx, remote_id) in [(0, 1), (1, 0)].iter().cloned() {
+		let node = &mut nodes[node_idx];
+		node.config.tombstone_cooldown = 0;
+		let remote_idx = node.index_by_node_id(&remote_id).unwrap();
+		node.close_session(remote_idx).unwrap();
+	}
+	assert_eq!(nodes[0].session_counts().total(), 0);
+	run(&mut nodes, 5);
+	assert!(session_id_with(&nodes[0], 1).is_some());
+	assert_eq!(session_id_with(&nodes[0], 1), session_id_with(&nodes[1], 0));
+}
+
+#[test]
+fn coord_query_resolves_batch_in_one_response() {
+	let mut nodes = chain(2);
+	for node_id in 2..5 {
+		learn_coord(&mut nodes[1], node_id, RouteCoord::new(node_id as i64 * 10, 5));
+	}
+	nodes[0].action(NodeAction::QueryCoords(1, vec![1, 2, 3, 4, 5]));
+	let sent_from = |packets: PacketVec, src_addr: NetAddr| -> PacketVec {
+		packets.into_iter().map(|mut packet| { packet.src_addr = src_addr; packet }).collect()
+	};
+	let query = sent_from(nodes[0].tick(PacketVec::new()), 0);
+	let responses: PacketVec = sent_from(nodes[1].tick(query), 1).into_iter()
+		.filter(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None),
+			Ok(NodeEncryption::Session { packet: NodePacket::CoordQueryResponse(..), .. })))
+		.collect();
+	assert_eq!(responses.len(), 1);
+
+	nodes[0].tick(responses);
+	let known = |node_id| nodes[0].index_by_node_id(&node_id).ok().and_then(|node_idx| nodes[0].remote(node_idx).ok()?.route_coord);
+	assert_eq!(known(1), Some(RouteCoord::new(10, 0)));
+	for node_id in 2..5 {
+		assert_eq!(known(node_id), Some(RouteCoord::new(node_id as i64 * 10, 5)));
+	}
+	assert_eq!(known(5), None);
+}
+
+#[test]
+fn equal_distances_keep_both_direct_sessions() {
+	let mut node = node_with_sessions(&[1, 2]);
+	for node_id in 1..3 {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.set_direct_dist(node_idx, node_id, 100);
+	}
+	let entries: Vec<(RouteScalar, NodeID)> = node.direct_sorted.keys().cloned().collect();
+	assert_eq!(entries, vec![(100, 1), (100, 2)]);
+}
+
+#[test]
+fn remeasured_direct_session_keeps_one_entry() {
+	let mut node = node_with_sessions(&[1]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	node.set_direct_dist(node_idx, 1, 100);
+	node.set_direct_dist(node_idx, 1, 40);
+	let entries: Vec<(RouteScalar, NodeID)> = node.direct_sorted.keys().cloned().collect();
+	assert_eq!(entries, vec![(40, 1)]);
+	node.close_session(node_idx).unwrap();
+	assert!(node.direct_sorted.is_empty());
+}
+
+#[test]
+fn unanswered_dht_read_fails_waiting_actions() {
+	let mut node = Node::new(0, 0);
+	node.config.dht_timeout = 10;
+	node.add_remote(9).unwrap();
+	node.action(NodeAction::Notify(9, 42));
+	// The DHT store is dead, requests are never answered
+	let mut reads = 0;
+	for _ in 0..50 {
+		reads += node.tick(PacketVec::new()).iter()
+			.filter(|packet| matches!(packet.request, Some(InternetRequest::RouteCoordDHTRead(9)))).count();
+	}
+	assert_eq!(reads, node.config.dht_attempts);
+	assert!(node.events().iter().any(|event| matches!(event, NodeEvent::RouteCoordLookupFailed { node_id: 9 })));
+	assert!(!node.action_list.iter().any(|action| matches!(action, NodeAction::Condition(..))));
+}
+
+#[test]
+fn relay_accepts_incoming_peers_beyond_outgoing_target() {
+	let remote_ids: Vec<NodeID> = (1..=8).collect();
+	let mut relay = node_with_sessions(&remote_ids);
+	relay.route_coord = Some(RouteCoord::new(0, 0));
+	relay.config.target_peer_count = 2;
+	relay.config.max_incoming_peers = 4;
+	for &node_id in &remote_ids { learn_coord(&mut relay, node_id, RouteCoord::new(node_id as i64, 10)); }
+	measure(&mut relay, &remote_ids, 20);
+	let mut outgoing = PacketVec::new();
+	relay.parse_action(NodeAction::CalculatePeers, &mut outgoing, &mut ActionVec::new()).unwrap();
+
+	let mut outgoing = PacketVec::new();
+	for &node_id in &remote_ids {
+		let node_idx = relay.index_by_node_id(&node_id).unwrap();
+		let notify = NodePacket::PeerNotify(0, RouteCoord::new(node_id as i64, 10), 1, 10);
+		relay.parse_node_packet(node_idx, notify, &mut outgoing, 0).unwrap();
+	}
+	assert_eq!(relay.peer_list.len(), 2);
+	assert_eq!(relay.incoming_peers(), vec![3, 4, 5, 6]);
+	// Remotes past the incoming budget are refused
+	let refused: Vec<NetAddr> = outgoing.iter()
+		.filter(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None),
+			Ok(NodeEncryption::Session { packet: NodePacket::PeerRefuse, .. })))
+		.map(|packet| packet.dest_addr).collect();
+	assert_eq!(refused, vec![7, 8]);
+}
+
+#[test]
+fn contradictory_coord_proposal_is_rejected() {
+	let mut node = node_with_sessions(&[1]);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	measure(&mut node, &[1], 20);
+	// Measured distance is 10, the proposal puts the remote 500 away
+	let mut outgoing = PacketVec::new();
+	node.parse_node_packet(node_idx, NodePacket::ProposeRouteCoords(RouteCoord::new(0, 0), RouteCoord::new(500, 0)), &mut outgoing, 0).unwrap();
+	assert_eq!(node.route_coord, None);
+	assert_eq!(node.remote(node_idx).unwrap().route_coord, None);
+
+	assert!(node.validate_and_adopt_coordinate(node_idx, RouteCoord::new(0, 0), RouteCoord::new(10, 0)).unwrap());
+	assert_eq!(node.route_coord, Some(RouteCoord::new(0, 0)));
+	assert_eq!(node.remote(node_idx).unwrap().route_coord, Some(RouteCoord::new(10, 0)));
+}
+
+#[test]
+fn accepted_proposal_is_retracted_if_not_adopted() {
+	// Proposer got a coordinate some other way while its proposal was in flight
+	let mut proposer = node_with_sessions(&[1]);
+	let node_idx = proposer.index_by_node_id(&1).unwrap();
+	proposer.route_coord = Some(RouteCoord::new(50, 50));
+	let mut outgoing = PacketVec::new();
+	proposer.parse_node_packet(node_idx, NodePacket::ProposeRouteCoordsResponse(RouteCoord::new(10, 0), RouteCoord::new(0, 0), true), &mut outgoing, 0).unwrap();
+	assert_eq!(proposer.route_coord, Some(RouteCoord::new(50, 50)));
+	assert!(outgoing.iter().any(|packet| matches!(NodeEncryption::unpackage(packet, Encryption::None),
+		Ok(NodeEncryption::Session { packet: NodePacket::RetractRouteCoords(..), .. }))));
+
+	// Acceptor drops the coordinates it adopted from the proposal
+	let mut acceptor = node_with_sessions(&[1]);
+	let node_idx = acceptor.index_by_node_id(&1).unwrap();
+	measure(&mut acceptor, &[1], 20);
+	assert!(acceptor.validate_and_adopt_coordinate(node_idx, RouteCoord::new(10, 0), RouteCoord::new(0, 0)).unwrap());
+	acceptor.parse_node_packet(node_idx, NodePacket::RetractRouteCoords(RouteCoord::new(10, 0), RouteCoord::new(0, 0)), &mut PacketVec::new(), 0).unwrap();
+	assert_eq!(acceptor.route_coord, None);
+	assert_eq!(acceptor.remote(node_idx).unwrap().route_coord, None);
+}
+
+#[test]
+fn sequence_runs_in_order_and_aborts_on_failure() {
+	let is_dht_read = |packet: &InternetPacket| matches!(packet.request, Some(InternetRequest::RouteCoordDHTRead(5)));
+	let steps = |middle: NodeAction| NodeAction::Sequence(vec![
+		NodeAction::Connect(1, SessionType::direct(1), vec![]),
+		middle,
+		NodeAction::RequestRouteCoord(5),
+	]);
+
+	let mut node = Node::new(0, 0);
+	let mut outgoing = PacketVec::new();
+	assert!(node.parse_action(steps(NodeAction::PruneRoutes), &mut outgoing, &mut ActionVec::new()).unwrap().is_none());
+	assert_eq!(outgoing.len(), 2);
+	assert_eq!(outgoing[0].dest_addr, 1);
+	assert!(is_dht_read(&outgoing[1]));
+
+	// Exchanging info with an unknown remote fails, so the DHT read never happens
+	let mut node = Node::new(0, 0);
+	let mut outgoing = PacketVec::new();
+	assert!(node.parse_action(steps(NodeAction::ExchangeInformation(99)), &mut outgoing, &mut ActionVec::new()).is_err());
+	assert_eq!(outgoing.len(), 1);
+	assert!(!is_dht_read(&outgoing[0]));
+}
+
+#[test]
+fn three_dimensional_coords_are_rejected() {
+	let encryption = NodeEncryption::Notify { recipient: 0, data: 0, sender: 1 };
+	let mut packet = encryption.package(0, Encryption::None);
+	packet.src_addr = 1;
+	assert!(NodeEncryption::unpackage(&packet, Encryption::None).is_ok());
+	// Sent by a node embedding in 3 dimensions
+	packet.data[1] = 3;
+	assert!(matches!(NodeEncryption::unpackage(&packet, Encryption::None),
+		Err(NodeError::CoordDimensionMismatch { expected: 2, received: Some(3) })));
+
+	let mut node = Node::new(0, 0);
+	node.config.strict = true;
+	assert!(matches!(node.try_tick(std::iter::once(packet).collect()), Err(NodeError::CoordDimensionMismatch { .. })));
+}
+
+#[test]
+fn flapping_peers_report_high_churn() {
+	let churn = |flapping: bool| {
+		let mut node = node_with_sessions(&[1]);
+		node.route_coord = Some(RouteCoord::new(0, 0));
+		learn_coord(&mut node, 1, RouteCoord::new(10, 0));
+		measure(&mut node, &[1], 20);
+		let recompute = |node: &mut Node| node.parse_action(NodeAction::CalculatePeers, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+		recompute(&mut node);
+		// Initial peer selection falls out of the window
+		node.ticks += node.config.churn_window;
+		for round in 0..10 {
+			if flapping && round % 2 == 0 { node.released_peers.insert(1); } else { node.released_peers.remove(&1); }
+			recompute(&mut node);
+			node.ticks += 100;
+		}
+		node.stats().peer_churn_rate
+	};
+	assert_eq!(churn(false), 0.0);
+	assert!(churn(true) >= 1.0);
+}
+
+#[test]
+fn malformed_session_packet_is_reported_to_sender() {
+	let mut nodes = chain(2);
+	nodes[0].config.max_packet_depth = 0;
+	let remote_idx = nodes[0].index_by_node_id(&1).unwrap();
+	nodes[0].remote_mut(remote_idx).unwrap().session_mut().unwrap().tracker.gen_ping(7, 0);
+	// Nesting any packet is too deep for node 0
+	let mut in_flight = PacketVec::new();
+	let node_idx = nodes[1].index_by_node_id(&0).unwrap();
+	nodes[1].send_packet(node_idx, NodePacket::ConnectionInit(7, vec![NodePacket::RequestCoord]), &mut in_flight).unwrap();
+	for packet in in_flight.iter_mut() { packet.src_addr = 1 }
+	deliver(&mut nodes, in_flight.into_iter().collect(), 3);
+	assert!(nodes[1].events().iter().any(|event| matches!(event, NodeEvent::ProtocolError { sender: 0, code: ErrorCode::Malformed, .. })));
+}
+
+#[test]
+fn lowering_target_peer_count_sheds_peers() {
+	let remote_ids: Vec<NodeID> = (1..=6).collect();
+	let mut node = node_with_sessions(&remote_ids);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	for &node_id in &remote_ids { learn_coord(&mut node, node_id, RouteCoord::new(node_id as i64 * 10, 0)); }
+	measure(&mut node, &remote_ids, 20);
+	node.parse_action(NodeAction::CalculatePeers, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+	assert_eq!(node.peer_list.len(), 6);
+
+	let mut invalid = node.config.clone();
+	invalid.target_peer_count = 0;
+	assert!(matches!(node.apply_config(invalid), Err(NodeError::InvalidConfig { .. })));
+	assert_eq!(node.config.target_peer_count, 10);
+
+	let mut config = node.config.clone();
+	config.target_peer_count = 2;
+	config.ping_window = 4;
+	node.apply_config(config).unwrap();
+	node.tick(PacketVec::new());
+	assert_eq!(node.peer_list.len(), 2);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	assert_eq!(node.remote(node_idx).unwrap().session().unwrap().tracker.window(), 4);
+}
+
+#[test]
+fn handshake_survives_lossy_link() {
+	use rand::Rng;
+	use std::sync::Mutex;
+	for seed in 0..5 {
+		let mut nodes = nodes(2);
+		nodes[1].config.handshake_retransmit_interval = 10;
+		nodes[1].config.handshake_timeout = 1000;
+		// Handshakes and Acknowledges both pass through node 1's filter
+		let rng = Mutex::new(SmallRng::seed_from_u64(seed));
+		nodes[1].set_drop_filter(move |_| rng.lock().unwrap().gen::<f64>() < 0.3);
+		nodes[1].action(NodeAction::Connect(0, SessionType::direct(0), vec![]));
+		run(&mut nodes, 1000);
+		let remote = nodes[1].remote(nodes[1].index_by_node_id(&0).unwrap()).unwrap();
+		assert!(remote.session().unwrap().setup_latency().unwrap() < 1000);
+	}
+}
+
+#[test]
+fn coordinate_history_records_each_calculation() {
+	let mut node = node_with_sessions(&[1, 2, 3]);
+	measure(&mut node, &[1, 2, 3], 20);
+	node.config.coordinate_history_size = 3;
+	let mut committed = Vec::new();
+	// Neighbors drift steadily in one direction so the coordinate never oscillates
+	for pull in 0..4 {
+		for &(node_id, x, y) in &[(1, 10, 0), (2, 0, 10), (3, -10, 0)] {
+			learn_coord(&mut node, node_id, RouteCoord::new(x + pull * 20, y));
+		}
+		node.parse_action(NodeAction::CalcRouteCoord, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+		committed.push((node.ticks, node.route_coord.unwrap()));
+		node.ticks += 10;
+	}
+	assert_eq!(node.coordinate_history(), &committed[1..]);
+}
+
+#[test]
+fn anchors_hold_coordinate_frame() {
+	// Far enough from origin that the frame would otherwise be recentered
+	let x = 1i64 << 40;
+	let mut anchor = node_with_sessions(&[1]);
+	anchor.set_anchor(RouteCoord::new(x, 0));
+	anchor.parse_action(NodeAction::CalcRouteCoord, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+	anchor.normalize_coordinates();
+	assert_eq!(anchor.route_coord, Some(RouteCoord::new(x, 0)));
+
+	let mut node = node_with_sessions(&[1, 2]);
+	measure(&mut node, &[1, 2], 20);
+	for &(node_id, anchor_x) in &[(1, x), (2, x + 16)] {
+		let node_idx = node.index_by_node_id(&node_id).unwrap();
+		node.parse_node_packet(node_idx, NodePacket::Anchor(Some(RouteCoord::new(anchor_x, 0))), &mut PacketVec::new(), 0).unwrap();
+	}
+	let mut coords = Vec::new();
+	for _ in 0..20 {
+		node.parse_action(NodeAction::CalcRouteCoord, &mut PacketVec::new(), &mut ActionVec::new()).unwrap();
+		coords.push(node.route_coord.unwrap());
+		node.ticks += 100;
+	}
+	assert!(coords.iter().all(|&route_coord| route_coord == coords[0]));
+	assert!((types::route_dist(&coords[0], &RouteCoord::new(x, 0)) - 10.0).abs() <= 1.0);
+	for &(node_id, anchor_x) in &[(1, x), (2, x + 16)] {
+		assert_eq!(node.remote(node.index_by_node_id(&node_id).unwrap()).unwrap().route_coord, Some(RouteCoord::new(anchor_x, 0)));
+	}
+}
+
+#[test]
+fn inconsistent_anchor_is_not_weighted() {
+	let mut node = node_with_sessions(&[1]);
+	node.route_coord = Some(RouteCoord::new(0, 0));
+	measure(&mut node, &[1], 20);
+	let node_idx = node.index_by_node_id(&1).unwrap();
+	let recalculations = |node: &Node| node.pending_actions().iter().filter(|action| matches!(action, NodeAction::CalcRouteCoord)).count();
+	let announce = |node: &mut Node, route_coord| node.parse_node_packet(node_idx, NodePacket::Anchor(Some(route_coord)), &mut PacketVec::new(), 0).unwrap();
+
+	// Measured 10 away, so an anchor claiming to be 1000 away is ignored
+	announce(&mut node, RouteCoord::new(1000, 0));
+	assert!(!node.remote(node_idx).unwrap().is_anchor);
+	assert_ne!(node.remote(node_idx).unwrap().route_coord, Some(RouteCoord::new(1000, 0)));
+	assert_eq!(recalculations(&node), 0);
+
+	announce(&mut node, RouteCoord::new(10, 0));
+	assert!(node.remote(node_idx).unwrap().is_anchor);
+	assert_eq!(recalculations(&node), 1);
+	// Repeating the same announcement changes nothing
+	announce(&mut node, RouteCoord::new(10, 0));
+	assert_eq!(recalculations(&node), 1);
+}
+
+#[test]
+fn own_dht_entry_creates_no_self_remote() {
+	let mut node = Node::new(0, 0);
+	node.public_route = Some(RouteCoord::new(5, 5));
+	let response = |route_coord| InternetPacket { src_addr: 0, dest_addr: 0, data: vec![], request: Some(InternetRequest::RouteCoordDHTReadResponse(0, route_coord)) };
+	node.handle_packet(response(Some(RouteCoord::new(5, 5)))).unwrap();
+	assert_eq!(node.public_route, Some(RouteCoord::new(5, 5)));
+	// A stale entry is republished instead of trusted
+	node.handle_packet(response(Some(RouteCoord::new(9, 9)))).unwrap();
+	assert_eq!(node.public_route, None);
+	assert!(node.index_by_node_id(&0).is_err());
+	assert!(node.remotes.is_empty());
+	assert!(!node.route_map.contains_edge(0, 0));
+}
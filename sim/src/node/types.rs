@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
-pub use crate::node::session::{RemoteSession, SessionError, SessionType, RoutedSession};
+pub use crate::node::session::{RemoteSession, SessionError, SessionKind, SessionType, RoutedSession};
+
+use std::convert::TryFrom;
 
 use vpsearch::MetricSpace;
 use nalgebra::Point2;
@@ -10,10 +12,13 @@ pub type NodeID = u32;
 /// Number uniquely identifying a session, represents a Symmetric key
 pub type SessionID = u32;
 /// Coordinate that represents a position of a node relative to other nodes in 2D space.
+/// `RouteScalar::MAX` is reserved as the sentinel for an unreachable or overflowing distance.
 pub type RouteScalar = u64;
 
 //#[repr(transparent)]
 pub type RouteCoord = Point2<i64>;
+/// Number of dimensions of a RouteCoord, sent with every packet so nodes embedding in a different number of dimensions are detected
+pub const ROUTE_COORD_DIMENSIONS: u8 = 2;
 
 pub struct RouteCoordStruct {
 	x: i64,
@@ -24,10 +29,65 @@ impl From<Point2<i64>> for RouteCoordStruct {
 		RouteCoordStruct { x: other[0], y: other[1] }
 	}
 }
+/// Conversion between integer route coordinates and the floating point coordinates used for distance math
+pub trait RouteCoordExt {
+	fn as_f64(&self) -> Point2<f64>;
+	/// Round a floating point coordinate to the nearest route coordinate
+	fn from_f64(point: Point2<f64>) -> Self;
+}
+impl RouteCoordExt for RouteCoord {
+	fn as_f64(&self) -> Point2<f64> { self.map(|s| s as f64) }
+	fn from_f64(point: Point2<f64>) -> Self { point.map(|s| s.round() as i64) }
+}
 pub fn route_dist(start: &RouteCoord, end: &RouteCoord) -> f64 {
-	let start_f64 = start.map(|s|s as f64);
-	let end_f64 = end.map(|s|s as f64);
-	nalgebra::distance(&start_f64, &end_f64)
+	nalgebra::distance(&start.as_f64(), &end.as_f64())
+}
+
+/// Squared distance between two coordinates, saturates to `RouteScalar::MAX` instead of overflowing
+pub fn route_dist_sq(start: &RouteCoord, end: &RouteCoord) -> RouteScalar {
+	let dx = (start.x as i128 - end.x as i128).unsigned_abs();
+	let dy = (start.y as i128 - end.y as i128).unsigned_abs();
+	let dist_sq = dx.checked_mul(dx).and_then(|dx_sq| dy.checked_mul(dy).and_then(|dy_sq| dx_sq.checked_add(dy_sq)));
+	dist_sq.map_or(RouteScalar::MAX, |d| RouteScalar::try_from(d).unwrap_or(RouteScalar::MAX))
+}
+/// Key of an entry in a DHT sharded by coordinate region
+pub type DhtKey = u64;
+/// Map a coordinate to its position along a Hilbert curve covering the `i32` coordinate range, so nearby coordinates get nearby keys.
+/// Coordinates outside the `i32` range are clamped to its edge.
+pub fn coordinate_to_dht_key(coord: RouteCoord) -> DhtKey {
+	// Shift signed axes into unsigned space so the curve covers negative coordinates
+	let to_axis = |s: i64| (s.max(i32::MIN as i64).min(i32::MAX as i64) as i32 as u32) ^ (1 << 31);
+	let (mut x, mut y) = (to_axis(coord.x), to_axis(coord.y));
+	let mut key: DhtKey = 0;
+	let mut s: u32 = 1 << 31;
+	while s > 0 {
+		let rx = (x & s) != 0;
+		let ry = (y & s) != 0;
+		key += (s as u64) * (s as u64) * ((3 * rx as u64) ^ ry as u64);
+		// Rotate quadrant so the curve stays continuous
+		if !ry {
+			if rx { x = !x; y = !y; }
+			std::mem::swap(&mut x, &mut y);
+		}
+		s >>= 1;
+	}
+	key
+}
+/// Distance of a link that hasn't been measured yet or can't be reached, sorts after every real distance
+pub const UNREACHABLE: RouteScalar = RouteScalar::MAX;
+/// Helpers for interpreting RouteScalar distances
+pub trait RouteScalarExt {
+	/// True if this distance is an actual measurement or estimate and not `UNREACHABLE`
+	fn is_known(self) -> bool;
+}
+impl RouteScalarExt for RouteScalar {
+	fn is_known(self) -> bool { self != UNREACHABLE }
+}
+/// Convert a floating point distance to a RouteScalar, NaN and out of range values become `RouteScalar::MAX`
+pub fn scalar_from_f64(distance: f64) -> RouteScalar {
+	if distance.is_nan() || distance >= RouteScalar::MAX as f64 { RouteScalar::MAX }
+	else if distance <= 0.0 { 0 }
+	else { distance as RouteScalar }
 }
 
 struct MyImpl;
@@ -41,4 +101,40 @@ impl MetricSpace<MyImpl> for RouteCoord {
 		let dy = self.y - other.y;
         f64::sqrt((dx*dx + dy*dy) as f64) // sqrt is required
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn huge_distances_order_correctly() {
+		let origin = RouteCoord::new(i64::MAX - 10, i64::MAX - 10);
+		let near = RouteCoord::new(i64::MAX, i64::MAX);
+		let mid = RouteCoord::new(i64::MAX - 1_000_000_000, i64::MAX);
+		let far = RouteCoord::new(i64::MIN, i64::MIN);
+		assert!(route_dist_sq(&origin, &near) < route_dist_sq(&origin, &mid));
+		assert!(route_dist_sq(&origin, &mid) < route_dist_sq(&origin, &far));
+		assert_eq!(route_dist_sq(&origin, &far), RouteScalar::MAX);
+		assert!(route_dist(&origin, &near) < route_dist(&origin, &far));
+	}
+
+	#[test]
+	fn coords_round_trip_through_f64() {
+		for &(x, y) in &[(0, 0), (-17, 42), (1 << 40, -(1 << 40))] {
+			let route_coord = RouteCoord::new(x, y);
+			assert_eq!(RouteCoord::from_f64(route_coord.as_f64()), route_coord);
+		}
+		assert_eq!(RouteCoord::from_f64(Point2::new(1.4, -1.6)), RouteCoord::new(1, -2));
+		assert_eq!(RouteCoord::from_f64(Point2::new(2.5, -2.5)), RouteCoord::new(3, -3));
+	}
+
+	#[test]
+	fn nearby_coords_get_nearby_dht_keys() {
+		let key = coordinate_to_dht_key;
+		let key_dist = |a: RouteCoord, b: RouteCoord| (key(a) as i128 - key(b) as i128).abs();
+		// Both points lie in the same 2x2 block of the curve
+		assert!(key_dist(RouteCoord::new(1000, 1000), RouteCoord::new(1001, 1001)) < 4);
+		assert!(key_dist(RouteCoord::new(1000, 1000), RouteCoord::new(1_000_000, -1_000_000)) > 1 << 32);
+		assert_ne!(key(RouteCoord::new(i64::MAX, 0)), key(RouteCoord::new(i64::MIN, 0)));
+	}
+}
@@ -0,0 +1,54 @@
+//! Counts heap allocations made while ticking, in its own test binary so the counting allocator doesn't apply to the rest of the test suite
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use sim::internet::{CustomNode, NetSimPacketVec};
+use sim::node::{NodeAction, NodeActionCondition};
+use sim::Node;
+
+/// Counts allocations per thread so tests running in parallel don't see each other's
+struct CountingAlloc;
+thread_local! {
+	static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+		System.alloc(layout)
+	}
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations() -> usize {
+	ALLOCATIONS.with(|count| count.get())
+}
+
+/// Allocations made by one tick of a node with `waiting` actions queued behind a condition that never holds,
+/// optionally throwing away the scratch buffers kept between ticks first
+fn tick_allocations(waiting: usize, reuse_buffers: bool) -> usize {
+	let mut node = Node::new(0, 0).with_seed(0);
+	for _ in 0..waiting {
+		node.action(NodeAction::CalcRouteCoord.gen_condition(NodeActionCondition::RunAt(usize::MAX)));
+	}
+	// First tick sizes the buffers
+	node.tick(NetSimPacketVec::new());
+	if !reuse_buffers { node.release_buffers() }
+	let before = allocations();
+	let outgoing = node.tick(NetSimPacketVec::new());
+	let count = allocations() - before;
+	drop(outgoing);
+	count
+}
+
+#[test]
+fn reused_buffers_allocate_less() {
+	// More waiting actions than ActionVec holds inline, so a released buffer has to grow again
+	let before = tick_allocations(40, false);
+	let after = tick_allocations(40, true);
+	assert!(after < before, "{} allocations with reused buffers, {} without", after, before);
+}